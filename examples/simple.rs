@@ -1,27 +1,24 @@
 use std::convert::Infallible;
 
+use std::time::Duration;
+
 use clap::Parser;
 
-use repellet::{ExecutionContext, ReplContext, ReplHandler, TermReader};
-use simplelog::{Config, TermLogger};
+use repellet::{ExecutionContext, ReplContext, ReplHandler, ReplLogger, TermReader};
 
 #[derive(Debug, Parser)]
 pub enum SimpleCli {
     Test { name: String },
     Clear,
     Panic,
+    Sleep { seconds: u64 },
 }
 
 pub fn main() {
     let reader = TermReader::new();
-    TermLogger::init(
-        log::LevelFilter::Info,
-        Config::default(),
-        simplelog::TerminalMode::Mixed,
-        simplelog::ColorChoice::Auto,
-    )
-    .unwrap();
-    let processor: ReplContext<SimpleCli, _> = ReplContext::new(reader, MyCommandHandler {});
+    ReplLogger::new(reader.printer()).init().unwrap();
+    let mut processor: ReplContext<SimpleCli, _> = ReplContext::new(reader, MyCommandHandler {});
+    processor.set_cancel_on_interrupt(true);
     processor
         .read_loop(repellet::default_error_handler)
         .unwrap();
@@ -40,6 +37,14 @@ impl ReplHandler<SimpleCli> for MyCommandHandler {
                 ctx.editor.clear_scrollback().unwrap();
             }
             SimpleCli::Panic => panic!("Panic Test"),
+            SimpleCli::Sleep { seconds } => {
+                for _ in 0..seconds * 10 {
+                    if ctx.is_cancelled() {
+                        break;
+                    }
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+            }
         }
         Ok(())
     }
@@ -0,0 +1,322 @@
+use clap::{Arg, ArgAction, Command};
+
+use crate::Token;
+
+/// What a single token of a command line was recognized as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TokenKind {
+    Subcommand,
+    UnknownSubcommand,
+    Flag,
+    UnknownFlag,
+    FlagValue,
+    Positional,
+    UnexpectedPositional,
+    Escape,
+    /// Tokens following an unknown subcommand
+    Invalid,
+}
+
+/// Result of walking the clap [`Command`] tree along the tokens of a command line
+pub(crate) struct Analysis<'a> {
+    pub kinds: Vec<TokenKind>,
+    /// The argument each token is a value of, for flag values and positionals
+    pub args: Vec<Option<&'a Arg>>,
+    /// The chain of subcommands that was entered, starting with the root command
+    pub commands: Vec<&'a Command>,
+    /// A flag that still expects a value in the next token
+    pub pending: Option<&'a Arg>,
+    /// Number of positional values that were already given to the current command
+    pub positionals: usize,
+    /// Ids of the flags that were already used on the current command
+    pub used: Vec<&'a str>,
+    pub only_positionals: bool,
+    pub valid: bool,
+}
+
+impl<'a> Analysis<'a> {
+    pub fn command(&self) -> &'a Command {
+        self.commands[self.commands.len() - 1]
+    }
+
+    /// The positional argument the next positional value would be assigned to
+    pub fn next_positional(&self) -> Option<&'a Arg> {
+        positional_at(self.command(), self.positionals)
+    }
+
+    /// Whether the next non-flag token may name a subcommand of the current command
+    pub fn expects_subcommand(&self) -> bool {
+        self.valid
+            && !self.only_positionals
+            && self.positionals == 0
+            && self.command().has_subcommands()
+    }
+
+    /// Whether `arg` may still be given on the current command
+    pub fn is_available(&self, arg: &Arg) -> bool {
+        matches!(arg.get_action(), ArgAction::Append | ArgAction::Count)
+            || !self.used.contains(&arg.get_id().as_str())
+    }
+}
+
+pub(crate) fn takes_value(arg: &Arg) -> bool {
+    arg.get_action().takes_values()
+}
+
+pub(crate) fn positional_at(command: &Command, index: usize) -> Option<&Arg> {
+    let positionals: Vec<_> = command.get_positionals().collect();
+    match positionals.get(index) {
+        Some(arg) => Some(arg),
+        None => positionals.last().copied().filter(|arg| {
+            arg.get_num_args()
+                .map(|range| range.max_values() > 1)
+                .unwrap_or(false)
+        }),
+    }
+}
+
+pub(crate) fn find_long<'a>(command: &'a Command, name: &str) -> Option<&'a Arg> {
+    command.get_arguments().find(|arg| {
+        arg.get_long() == Some(name)
+            || arg
+                .get_all_aliases()
+                .map(|aliases| aliases.contains(&name))
+                .unwrap_or(false)
+    })
+}
+
+pub(crate) fn find_short(command: &Command, short: char) -> Option<&Arg> {
+    command.get_arguments().find(|arg| {
+        arg.get_short() == Some(short)
+            || arg
+                .get_all_short_aliases()
+                .map(|aliases| aliases.contains(&short))
+                .unwrap_or(false)
+    })
+}
+
+/// Walks `root` along `tokens` and classifies every token
+pub(crate) fn analyze<'a>(root: &'a Command, tokens: &[Token]) -> Analysis<'a> {
+    let mut analysis = Analysis {
+        kinds: Vec::with_capacity(tokens.len()),
+        args: Vec::with_capacity(tokens.len()),
+        commands: vec![root],
+        pending: None,
+        positionals: 0,
+        used: Vec::new(),
+        only_positionals: false,
+        valid: true,
+    };
+
+    for token in tokens {
+        let pending = analysis.pending;
+        let positional = analysis.next_positional();
+        let kind = analyze_token(&mut analysis, &token.value);
+        let arg = match kind {
+            TokenKind::FlagValue => pending,
+            TokenKind::Positional => positional,
+            _ => None,
+        };
+        analysis.kinds.push(kind);
+        analysis.args.push(arg);
+    }
+    analysis
+}
+
+fn analyze_token(analysis: &mut Analysis, value: &str) -> TokenKind {
+    if !analysis.valid {
+        return TokenKind::Invalid;
+    }
+    if analysis.pending.take().is_some() {
+        return TokenKind::FlagValue;
+    }
+    let command = analysis.command();
+
+    if !analysis.only_positionals {
+        if value == "--" {
+            analysis.only_positionals = true;
+            return TokenKind::Escape;
+        }
+        if let Some(long) = value.strip_prefix("--") {
+            let (name, inline_value) = match long.split_once('=') {
+                Some((name, _)) => (name, true),
+                None => (long, false),
+            };
+            return match find_long(command, name) {
+                Some(arg) => {
+                    analysis.used.push(arg.get_id().as_str());
+                    if takes_value(arg) && !inline_value {
+                        analysis.pending = Some(arg);
+                    }
+                    TokenKind::Flag
+                }
+                None => TokenKind::UnknownFlag,
+            };
+        }
+        if let Some(shorts) = value.strip_prefix('-').filter(|shorts| !shorts.is_empty()) {
+            for (idx, short) in shorts.char_indices() {
+                let Some(arg) = find_short(command, short) else {
+                    return TokenKind::UnknownFlag;
+                };
+                analysis.used.push(arg.get_id().as_str());
+                if takes_value(arg) {
+                    // The rest of the cluster is the value of the flag
+                    if idx + short.len_utf8() == shorts.len() {
+                        analysis.pending = Some(arg);
+                    }
+                    break;
+                }
+            }
+            return TokenKind::Flag;
+        }
+    }
+
+    if analysis.expects_subcommand() {
+        if let Some(subcommand) = command.find_subcommand(value) {
+            analysis.commands.push(subcommand);
+            analysis.positionals = 0;
+            analysis.used.clear();
+            return TokenKind::Subcommand;
+        }
+        if command.get_positionals().next().is_none() {
+            analysis.valid = false;
+            return TokenKind::UnknownSubcommand;
+        }
+    }
+
+    let kind = match analysis.next_positional() {
+        Some(_) => TokenKind::Positional,
+        None => TokenKind::UnexpectedPositional,
+    };
+    analysis.positionals += 1;
+    kind
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ShellTokenizer, Tokenizer};
+
+    fn command() -> Command {
+        let mut command = Command::new("repl").multicall(true).subcommand(
+            Command::new("connect")
+                .arg(Arg::new("host").required(true))
+                .arg(Arg::new("port").required(true))
+                .arg(Arg::new("protocol").short('p').long("protocol"))
+                .arg(
+                    Arg::new("verbose")
+                        .short('v')
+                        .long("verbose")
+                        .action(ArgAction::SetTrue),
+                ),
+        );
+        command.build();
+        command
+    }
+
+    fn kinds(command: &Command, line: &str) -> Vec<TokenKind> {
+        let tokens = ShellTokenizer.tokenize(line).unwrap();
+        analyze(command, &tokens).kinds
+    }
+
+    fn arg_ids(command: &Command, line: &str) -> Vec<Option<String>> {
+        let tokens = ShellTokenizer.tokenize(line).unwrap();
+        let analysis = analyze(command, &tokens);
+        analysis
+            .args
+            .iter()
+            .map(|arg| arg.map(|arg| arg.get_id().to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn flag_expects_value() {
+        let command = command();
+        let tokens = ShellTokenizer.tokenize("connect -p").unwrap();
+        let analysis = analyze(&command, &tokens);
+        assert_eq!(analysis.kinds, [TokenKind::Subcommand, TokenKind::Flag]);
+        assert_eq!(analysis.pending.map(Arg::get_id), Some(&"protocol".into()));
+        assert_eq!(analysis.command().get_name(), "connect");
+
+        assert_eq!(
+            kinds(&command, "connect -vp tcp host"),
+            [
+                TokenKind::Subcommand,
+                TokenKind::Flag,
+                TokenKind::FlagValue,
+                TokenKind::Positional
+            ]
+        );
+        assert_eq!(
+            arg_ids(&command, "connect -p tcp host 1"),
+            [
+                None,
+                None,
+                Some("protocol".into()),
+                Some("host".into()),
+                Some("port".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn inline_value_is_not_pending() {
+        let command = command();
+        let tokens = ShellTokenizer.tokenize("connect --protocol=tcp").unwrap();
+        let analysis = analyze(&command, &tokens);
+        assert_eq!(analysis.kinds, [TokenKind::Subcommand, TokenKind::Flag]);
+        assert!(analysis.pending.is_none());
+        assert_eq!(analysis.used, ["protocol"]);
+        let protocol = find_long(analysis.command(), "protocol").unwrap();
+        assert!(!analysis.is_available(protocol));
+
+        assert_eq!(
+            kinds(&command, "connect -ptcp host"),
+            [
+                TokenKind::Subcommand,
+                TokenKind::Flag,
+                TokenKind::Positional
+            ]
+        );
+    }
+
+    #[test]
+    fn escape_ends_flags() {
+        let command = command();
+        assert_eq!(
+            kinds(&command, "connect -- -v 1 2"),
+            [
+                TokenKind::Subcommand,
+                TokenKind::Escape,
+                TokenKind::Positional,
+                TokenKind::Positional,
+                TokenKind::UnexpectedPositional
+            ]
+        );
+        assert_eq!(
+            kinds(&command, "connect --port -x"),
+            [
+                TokenKind::Subcommand,
+                TokenKind::UnknownFlag,
+                TokenKind::UnknownFlag
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_subcommand_invalidates_line() {
+        let command = command();
+        let tokens = ShellTokenizer.tokenize("disconnect host -v").unwrap();
+        let analysis = analyze(&command, &tokens);
+        assert_eq!(
+            analysis.kinds,
+            [
+                TokenKind::UnknownSubcommand,
+                TokenKind::Invalid,
+                TokenKind::Invalid
+            ]
+        );
+        assert!(!analysis.valid);
+        assert!(!analysis.expects_subcommand());
+    }
+}
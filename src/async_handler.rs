@@ -0,0 +1,368 @@
+use std::fmt::{Debug, Display};
+use std::future::Future;
+use std::io::{BufRead, IsTerminal};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use clap::Command;
+use reedline::{DefaultPrompt, Reedline, Signal};
+use tokio::runtime::RuntimeFlavor;
+
+use crate::jobs::{self, SharedHandler};
+use crate::script::{exit_status, is_comment, script_error};
+use crate::{cancel, panic, PanicReport};
+use crate::{
+    CancellationToken, ControlFlow, ErrorContext, ErrorHandler, ExecutionContext, ExitStatus,
+    Handler, ReplContext, ReplError, ScriptErrorPolicy, TermReader,
+};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A [`ReplHandler`](crate::ReplHandler) whose commands are executed on the tokio runtime by
+/// [`ReplContext::read_loop_async`]
+pub trait AsyncReplHandler<C: clap::Parser> {
+    type Err: Debug + Display;
+    fn on_command<'a>(
+        &'a self,
+        ctx: &'a mut ExecutionContext<'_>,
+        command: C,
+    ) -> BoxFuture<'a, Result<(), Self::Err>>;
+}
+
+impl<C: clap::Parser + Debug, Err: Debug + Display> ReplContext<C, Err> {
+    /// Creates a context for an [`AsyncReplHandler`].
+    ///
+    /// Methods that execute commands synchronously, like [`ReplContext::run_script`], block on
+    /// the handler. Inside of a runtime this requires the multi-threaded scheduler, the async
+    /// methods like [`ReplContext::run_script_async`] work with either one.
+    pub fn new_async(
+        reader: TermReader,
+        handler: impl AsyncReplHandler<C, Err = Err> + Send + Sync + 'static,
+    ) -> Self
+    where
+        C: 'static,
+        Err: 'static,
+    {
+        let handler = Arc::new(handler);
+        let spawn_job = jobs::spawner(SharedHandler::Async(handler.clone()));
+        Self::with_handler(reader, Handler::Async(handler), Some(spawn_job))
+    }
+
+    /// Like [`ReplContext::read_loop`], but reads lines on a blocking thread and awaits the
+    /// commands on the current runtime.
+    pub async fn read_loop_async<F: ErrorHandler<Err>>(
+        mut self,
+        handle_error: F,
+    ) -> Result<ExitStatus, ReplError<Err>> {
+        if !std::io::stdin().is_terminal() {
+            return self.read_plain_loop_async(handle_error).await;
+        }
+        let _loop = cancel::enter_loop();
+        let command = &mut self.command.clone();
+        loop {
+            match self.read_with_command_async(command).await {
+                Ok(ControlFlow::Continue) => {}
+                Ok(ControlFlow::Exit(status)) => return Ok(status),
+                Ok(ControlFlow::Restart) => self.restart(command),
+                Err(err) => {
+                    let line = self.last_line.take();
+                    let mut context = self.error_context(command, line.as_deref());
+                    match handle_error.handle(&mut context, err) {
+                        Ok(()) => {}
+                        Err(ReplError::EOF) => return Ok(ExitStatus::SUCCESS),
+                        Err(err) => return Err(err),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Like [`ReplContext::run_script`], but awaits the commands on the current runtime
+    pub async fn run_script_async<F: ErrorHandler<Err>>(
+        &mut self,
+        path: impl AsRef<Path>,
+        handle_error: F,
+    ) -> Result<ExitStatus, ReplError<Err>> {
+        let mut command = self.command.clone();
+        let policy = self.script_error_policy;
+        let mut failed = false;
+        let on_error = |context: &mut ErrorContext<'_>, error| {
+            failed = true;
+            match policy {
+                ScriptErrorPolicy::Stop => Err(error),
+                ScriptErrorPolicy::Continue => handle_error.handle(context, error),
+            }
+        };
+        let flow = self
+            .run_file_async(&mut command, path.as_ref(), on_error)
+            .await?;
+        Ok(exit_status(flow, failed))
+    }
+
+    /// Like [`ReplContext::run_lines`], but awaits the commands on the current runtime
+    pub async fn run_lines_async<F: ErrorHandler<Err>>(
+        &mut self,
+        lines: impl IntoIterator<Item = impl AsRef<str>>,
+        handle_error: F,
+    ) -> Result<ExitStatus, ReplError<Err>> {
+        let mut command = self.command.clone();
+        let policy = self.script_error_policy;
+        let mut failed = false;
+        let on_error = |context: &mut ErrorContext<'_>, error| {
+            failed = true;
+            match policy {
+                ScriptErrorPolicy::Stop => Err(error),
+                ScriptErrorPolicy::Continue => handle_error.handle(context, error),
+            }
+        };
+        let mut lines = lines.into_iter();
+        let next_line = move || {
+            let line = lines.next().map(|line| Ok(line.as_ref().to_string()));
+            Box::pin(std::future::ready(line)) as NextLine
+        };
+        let flow = self
+            .run_script_lines_async(&mut command, None, next_line, on_error)
+            .await?;
+        Ok(exit_status(flow, failed))
+    }
+
+    /// Executes the lines of stdin like [`ReplContext::read_plain_loop`], reading them on a
+    /// blocking thread
+    async fn read_plain_loop_async<F: ErrorHandler<Err>>(
+        &mut self,
+        handle_error: F,
+    ) -> Result<ExitStatus, ReplError<Err>> {
+        let _loop = cancel::enter_loop();
+        self.interactive = false;
+        let mut command = self.command.clone();
+        let mut failed = false;
+        let on_error = |context: &mut ErrorContext<'_>, error| {
+            failed = true;
+            handle_error.handle(context, error)
+        };
+        let flow = self
+            .run_script_lines_async(&mut command, None, read_stdin_line, on_error)
+            .await;
+        self.interactive = true;
+        match flow {
+            Ok(flow) => Ok(exit_status(flow, failed)),
+            Err(ReplError::EOF) => Ok(exit_status(ControlFlow::Continue, failed)),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Executes the file at `path` for the `source` builtin like [`ReplContext::source`]
+    async fn source_async(
+        &mut self,
+        command: &mut Command,
+        path: &Path,
+    ) -> Result<ControlFlow, ReplError<Err>> {
+        self.run_file_async(command, path, |_, error| Err(error))
+            .await
+    }
+
+    async fn run_file_async(
+        &mut self,
+        command: &mut Command,
+        path: &Path,
+        on_error: impl FnMut(&mut ErrorContext<'_>, ReplError<Err>) -> Result<(), ReplError<Err>>,
+    ) -> Result<ControlFlow, ReplError<Err>> {
+        let mut lines = self.open_script(path)?;
+        let next_line = move || Box::pin(std::future::ready(lines.next())) as NextLine;
+        let result = self
+            .run_script_lines_async(command, Some(path), next_line, on_error)
+            .await;
+        self.source_stack.pop();
+        result
+    }
+
+    /// Executes the lines returned by `next_line` like [`ReplContext::run_script_lines`], awaiting
+    /// the commands
+    async fn run_script_lines_async(
+        &mut self,
+        command: &mut Command,
+        file: Option<&Path>,
+        mut next_line: impl FnMut() -> NextLine,
+        mut on_error: impl FnMut(&mut ErrorContext<'_>, ReplError<Err>) -> Result<(), ReplError<Err>>,
+    ) -> Result<ControlFlow, ReplError<Err>> {
+        let mut idx = 0;
+        while let Some(line) = next_line().await {
+            let (line, result) = match line {
+                Ok(line) => {
+                    let line = line.trim().to_string();
+                    let result = if is_comment(&line) {
+                        Ok(ControlFlow::Continue)
+                    } else {
+                        self.execute_line_async(command, &line).await
+                    };
+                    (Some(line), result)
+                }
+                Err(err) => (None, Err(ReplError::from(err))),
+            };
+
+            match result {
+                Ok(ControlFlow::Continue) => {}
+                Ok(ControlFlow::Exit(status)) => return Ok(ControlFlow::Exit(status)),
+                Ok(ControlFlow::Restart) => self.restart(command),
+                Err(error) => {
+                    let error = script_error(file, idx, error);
+                    let mut context = self.error_context(command, line.as_deref());
+                    on_error(&mut context, error)?
+                }
+            }
+            idx += 1;
+        }
+        Ok(ControlFlow::Continue)
+    }
+
+    pub async fn read_with_command_async(
+        &mut self,
+        command: &mut Command,
+    ) -> Result<ControlFlow, ReplError<Err>> {
+        self.last_line = None;
+        match self.read_line_blocking().await? {
+            Signal::Success(buffer) => {
+                let buffer = self.accept_line(buffer)?;
+                #[cfg(feature = "sqlite")]
+                let started = crate::history::CommandStart::now();
+                let res = self.execute_line_async(command, &buffer).await;
+                #[cfg(feature = "sqlite")]
+                started.record(&mut self.reader.editor, res.is_ok());
+                res
+            }
+            Signal::CtrlC => Err(ReplError::Interrupt),
+            Signal::CtrlD => Err(ReplError::EOF),
+        }
+    }
+
+    /// Moves the editor and prompt to a blocking thread for reading the next line
+    async fn read_line_blocking(&mut self) -> Result<Signal, ReplError<Err>> {
+        let mut editor = std::mem::replace(&mut self.reader.editor, Reedline::create());
+        let prompt = std::mem::replace(&mut self.reader.prompt, Box::new(DefaultPrompt::default()));
+        let prompt_guard = self.reader.printer.activate_prompt();
+        let (editor, prompt, signal) = tokio::task::spawn_blocking(move || {
+            let signal = editor.read_line(&*prompt);
+            drop(prompt_guard);
+            (editor, prompt, signal)
+        })
+        .await
+        .map_err(|err| match err.try_into_panic() {
+            Ok(payload) => ReplError::Panic(PanicReport::from_payload(payload)),
+            Err(err) => ReplError::Io(std::io::Error::other(err)),
+        })?;
+        self.reader.editor = editor;
+        self.reader.prompt = prompt;
+        Ok(signal?)
+    }
+
+    /// Executes `line`, dropping the future of the handler once Ctrl-C is pressed
+    async fn execute_line_async(
+        &mut self,
+        command: &mut Command,
+        line: &str,
+    ) -> Result<ControlFlow, ReplError<Err>> {
+        let token = CancellationToken::new();
+        self.cancellation = token.clone();
+        let _guard = cancel::watch(&token, self.cancel_on_interrupt);
+        let result = self.execute_command_async(command, line, &token).await;
+        if token.is_cancelled() {
+            Err(ReplError::Cancelled)
+        } else {
+            result
+        }
+    }
+
+    async fn execute_command_async(
+        &mut self,
+        command: &mut Command,
+        line: &str,
+        token: &CancellationToken,
+    ) -> Result<ControlFlow, ReplError<Err>> {
+        let cli_raw = match self.parse_line(command, line)? {
+            Some((cli_raw, true)) if !self.is_builtin(&cli_raw) => {
+                return self.spawn_job(command, cli_raw, line)
+            }
+            Some((cli_raw, _)) => cli_raw,
+            None => return Ok(ControlFlow::Continue),
+        };
+        if let Some(path) = self.source_path(&cli_raw) {
+            return Box::pin(self.source_async(command, path)).await;
+        }
+        if let Some(result) = self.execute_builtin(command, &cli_raw) {
+            return result;
+        }
+        let cli = C::from_arg_matches(&cli_raw).map_err(ReplError::Parse)?;
+        let (handler, mut context) = self.execution_context(command);
+        let result = match handler {
+            Handler::Sync(handler) => panic::catch(|| handler.on_command(&mut context, cli)),
+            Handler::Async(handler) => {
+                let future = CatchUnwind(handler.on_command(&mut context, cli));
+                tokio::select! {
+                    result = future => result,
+                    _ = token.cancelled() => return Err(ReplError::Cancelled),
+                }
+            }
+        };
+        match result {
+            Ok(result) => result
+                .map(|_| context.control_flow)
+                .map_err(ReplError::ExecutionError),
+            Err(report) => Err(ReplError::Panic(report)),
+        }
+    }
+}
+
+/// The next line of a script, `None` at its end
+type NextLine = BoxFuture<'static, Option<std::io::Result<String>>>;
+
+/// Reads the next line of stdin on a blocking thread
+fn read_stdin_line() -> NextLine {
+    Box::pin(async {
+        let line = tokio::task::spawn_blocking(|| {
+            let mut line = String::new();
+            match std::io::stdin().lock().read_line(&mut line) {
+                Ok(0) => None,
+                Ok(_) => Some(Ok(line)),
+                Err(err) => Some(Err(err)),
+            }
+        });
+        line.await
+            .unwrap_or_else(|err| Some(Err(std::io::Error::other(err))))
+    })
+}
+
+/// Runs `future` to completion from synchronous code.
+///
+/// Inside of a runtime the current thread is blocked, which fails unless the runtime uses the
+/// multi-threaded scheduler.
+pub(crate) fn block_on<F: Future>(future: F) -> std::io::Result<F::Output> {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            Ok(tokio::task::block_in_place(|| handle.block_on(future)))
+        }
+        Ok(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "async commands can only be executed synchronously on a multi-threaded runtime",
+        )),
+        Err(_) => Ok(tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(future)),
+    }
+}
+
+/// Turns a panic while polling the inner future into an error
+struct CatchUnwind<F>(F);
+
+impl<F: Future + Unpin> Future for CatchUnwind<F> {
+    type Output = Result<F::Output, PanicReport>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match panic::catch(|| Pin::new(&mut self.0).poll(cx)) {
+            Ok(poll) => poll.map(Ok),
+            Err(report) => Poll::Ready(Err(report)),
+        }
+    }
+}
@@ -0,0 +1,405 @@
+use std::fmt::{Debug, Display};
+use std::path::PathBuf;
+
+use clap::error::ErrorKind;
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command, ValueHint};
+use reedline::{History, HistoryItem, SearchDirection, SearchQuery};
+
+use crate::jobs::{Jobs, NoSuchJob};
+#[cfg(any(feature = "log", feature = "tracing-subscriber"))]
+use crate::LogFilter;
+use crate::{ControlFlow, ExecutionContext, ExitStatus, ReplError};
+
+/// Commands provided by repellet that can be merged into the command of a
+/// [`ReplContext`](crate::ReplContext) with [`ReplContext::enable_builtins`](crate::ReplContext::enable_builtins)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Builtin {
+    /// `history [N] [--grep PATTERN]` and `history clear`, also enables bang expansion
+    /// (`!!`, `!N`, `!prefix`) of command lines
+    History,
+    /// `exit [CODE]` and its alias `quit`, which end the read loop with the given exit code
+    Exit,
+    /// `help [COMMAND]...`, replacing the help subcommand generated by clap so that help is
+    /// printed through the [`ExecutionContext`] instead of being returned as an error
+    Help,
+    /// `source FILE`, which executes the commands in a file like a script. Errors stop the
+    /// file and are reported with the file and line they occurred in.
+    Source,
+    /// `jobs`, listing the running background jobs. Also allows running commands of the
+    /// handler in the background by appending `&` to the command line, which builtins ignore.
+    /// Requires a context created with [`ReplContext::new_shared`](crate::ReplContext::new_shared)
+    /// or `ReplContext::new_async`.
+    Jobs,
+    /// `wait [ID]`, which waits for a background job or all of them to finish
+    Wait,
+    /// `kill ID`, which cancels the [`CancellationToken`](crate::CancellationToken) of a
+    /// background job
+    Kill,
+    /// `loglevel [DIRECTIVE]...` and `loglevel --reset`, which list or change the
+    /// [`LogFilter`] set with [`ReplContext::set_log_filter`](crate::ReplContext::set_log_filter)
+    #[cfg(any(feature = "log", feature = "tracing-subscriber"))]
+    LogLevel,
+}
+
+impl Builtin {
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::History => "history",
+            Builtin::Exit => "exit",
+            Builtin::Help => "help",
+            Builtin::Source => "source",
+            Builtin::Jobs => "jobs",
+            Builtin::Wait => "wait",
+            Builtin::Kill => "kill",
+            #[cfg(any(feature = "log", feature = "tracing-subscriber"))]
+            Builtin::LogLevel => "loglevel",
+        }
+    }
+
+    pub(crate) fn command(self) -> Command {
+        match self {
+            Builtin::History => Command::new("history")
+                .about("Show or clear the command history")
+                .args_conflicts_with_subcommands(true)
+                .arg(
+                    Arg::new("count")
+                        .value_name("N")
+                        .value_parser(value_parser!(usize))
+                        .help("Only show the last N entries"),
+                )
+                .arg(
+                    Arg::new("grep")
+                        .long("grep")
+                        .value_name("PATTERN")
+                        .help("Only show entries containing PATTERN"),
+                )
+                .subcommand(Command::new("clear").about("Clear the command history")),
+            Builtin::Exit => Command::new("exit")
+                .visible_alias("quit")
+                .about("Exit the REPL")
+                .arg(
+                    Arg::new("code")
+                        .value_name("CODE")
+                        .value_parser(value_parser!(i32))
+                        .allow_negative_numbers(true)
+                        .help("The exit code"),
+                ),
+            Builtin::Help => Command::new("help")
+                .about("Print this message or the help of the given subcommand(s)")
+                .arg(
+                    Arg::new("command")
+                        .value_name("COMMAND")
+                        .action(ArgAction::Append)
+                        .num_args(0..)
+                        .help("Print help for the subcommand(s)"),
+                ),
+            Builtin::Source => Command::new("source")
+                .about("Execute the commands in a file")
+                .arg(
+                    Arg::new("file")
+                        .value_name("FILE")
+                        .value_parser(value_parser!(PathBuf))
+                        .value_hint(ValueHint::FilePath)
+                        .required(true)
+                        .help("The file to execute"),
+                ),
+            Builtin::Jobs => Command::new("jobs").about("List the running background jobs"),
+            Builtin::Wait => Command::new("wait")
+                .about("Wait for background jobs to finish")
+                .arg(
+                    Arg::new("id")
+                        .value_name("ID")
+                        .value_parser(value_parser!(usize))
+                        .help("The job to wait for instead of all jobs"),
+                ),
+            Builtin::Kill => Command::new("kill").about("Cancel a background job").arg(
+                Arg::new("id")
+                    .value_name("ID")
+                    .value_parser(value_parser!(usize))
+                    .required(true)
+                    .help("The job to cancel"),
+            ),
+            #[cfg(any(feature = "log", feature = "tracing-subscriber"))]
+            Builtin::LogLevel => Command::new("loglevel")
+                .about("Show or change which log messages are printed")
+                .arg(
+                    Arg::new("directive")
+                        .value_name("DIRECTIVE")
+                        .action(ArgAction::Append)
+                        .num_args(0..)
+                        .help("LEVEL or TARGET=LEVEL, where LEVEL is one of error, warn, info, debug, trace or off"),
+                )
+                .arg(
+                    Arg::new("reset")
+                        .long("reset")
+                        .action(ArgAction::SetTrue)
+                        .help("Restore the levels the REPL was started with"),
+                ),
+        }
+    }
+
+    pub(crate) fn run<Err: Debug + Display>(
+        self,
+        ctx: &mut ExecutionContext,
+        matches: &ArgMatches,
+        jobs: &Jobs,
+    ) -> Result<ControlFlow, ReplError<Err>> {
+        match self {
+            Builtin::History => run_history(ctx, matches).map(|_| ControlFlow::Continue),
+            Builtin::Exit => Ok(ControlFlow::Exit(ExitStatus(
+                matches.get_one::<i32>("code").copied().unwrap_or(0),
+            ))),
+            Builtin::Help => run_help(ctx, matches).map(|_| ControlFlow::Continue),
+            Builtin::Source => unreachable!("source is executed by the ReplContext"),
+            #[cfg(any(feature = "log", feature = "tracing-subscriber"))]
+            Builtin::LogLevel => unreachable!("loglevel is executed by the ReplContext"),
+            Builtin::Jobs => {
+                let list = jobs.list();
+                if !list.is_empty() {
+                    ctx.print(list.join("\n"));
+                }
+                Ok(ControlFlow::Continue)
+            }
+            Builtin::Wait => {
+                let id = matches.get_one::<usize>("id").copied();
+                let token = ctx.cancellation_token().clone();
+                jobs.wait(id, &token).map_err(|err| job_error(ctx, err))?;
+                Ok(ControlFlow::Continue)
+            }
+            Builtin::Kill => {
+                let id = *matches.get_one::<usize>("id").unwrap();
+                jobs.kill(id).map_err(|err| job_error(ctx, err))?;
+                Ok(ControlFlow::Continue)
+            }
+        }
+    }
+}
+
+fn job_error<Err: Debug + Display>(ctx: &mut ExecutionContext, err: NoSuchJob) -> ReplError<Err> {
+    ReplError::Clap(ctx.error(ErrorKind::InvalidValue, err))
+}
+
+/// Executes the `loglevel` builtin, `filter` is `None` if no filter was set on the
+/// [`ReplContext`](crate::ReplContext)
+#[cfg(any(feature = "log", feature = "tracing-subscriber"))]
+pub(crate) fn run_loglevel<Err: Debug + Display>(
+    ctx: &mut ExecutionContext,
+    matches: &ArgMatches,
+    filter: Option<&LogFilter>,
+) -> Result<ControlFlow, ReplError<Err>> {
+    let Some(filter) = filter else {
+        let message = "no log filter is configured";
+        return Err(ReplError::Clap(ctx.error(ErrorKind::InvalidValue, message)));
+    };
+    if matches.get_flag("reset") {
+        filter.reset();
+    }
+    let directives: Vec<_> = matches
+        .get_many::<String>("directive")
+        .into_iter()
+        .flatten()
+        .map(String::as_str)
+        .collect();
+    if !directives.is_empty() {
+        filter
+            .set(&directives.join(","))
+            .map_err(|err| ReplError::Clap(ctx.error(ErrorKind::InvalidValue, err)))?;
+    } else if !matches.get_flag("reset") {
+        ctx.print(filter.directives().join("\n"));
+    }
+    Ok(ControlFlow::Continue)
+}
+
+fn run_help<Err: Debug + Display>(
+    ctx: &mut ExecutionContext,
+    matches: &ArgMatches,
+) -> Result<(), ReplError<Err>> {
+    let colored = ctx.printer_handle().is_colored();
+    let mut command = &mut *ctx.command;
+    for name in matches.get_many::<String>("command").into_iter().flatten() {
+        if command.find_subcommand(name).is_none() {
+            let message = format!("unrecognized subcommand '{name}'");
+            return Err(ReplError::Clap(
+                command.error(ErrorKind::InvalidSubcommand, message),
+            ));
+        }
+        command = command.find_subcommand_mut(name).unwrap();
+    }
+    let help = if colored {
+        command.render_help().ansi().to_string()
+    } else {
+        command.render_help().to_string()
+    };
+    ctx.print(help);
+    Ok(())
+}
+
+fn history_error(err: impl Display) -> std::io::Error {
+    std::io::Error::other(err.to_string())
+}
+
+/// All history entries, oldest first
+fn history_entries(history: &dyn History) -> Result<Vec<HistoryItem>, std::io::Error> {
+    history
+        .search(SearchQuery::everything(SearchDirection::Forward, None))
+        .map_err(history_error)
+}
+
+fn run_history<Err: Debug + Display>(
+    ctx: &mut ExecutionContext,
+    matches: &ArgMatches,
+) -> Result<(), ReplError<Err>> {
+    if let Some(("clear", _)) = matches.subcommand() {
+        ctx.editor.history_mut().clear().map_err(history_error)?;
+        return Ok(());
+    }
+
+    let entries = history_entries(ctx.editor.history())?;
+    let grep = matches.get_one::<String>("grep");
+    let mut listed: Vec<_> = entries
+        .iter()
+        .enumerate()
+        .filter(|(_, item)| grep.is_none_or(|pattern| item.command_line.contains(pattern)))
+        .collect();
+    if let Some(count) = matches.get_one::<usize>("count") {
+        listed.drain(..listed.len().saturating_sub(*count));
+    }
+
+    let width = entries.len().to_string().len();
+    let output = listed
+        .into_iter()
+        .map(|(idx, item)| format!("{:>width$}  {}", idx + 1, item.command_line))
+        .collect::<Vec<_>>()
+        .join("\n");
+    if !output.is_empty() {
+        ctx.print(output);
+    }
+    Ok(())
+}
+
+/// Resolves a leading history designator of `line`.
+///
+/// `!!` is replaced with the previous command, `!N` with the N-th entry listed by the
+/// `history` builtin and `!prefix` with the most recent command starting with `prefix`.
+/// Anything after the designator is appended to the resolved command. The most recent
+/// history entry is skipped if it is `line` itself.
+pub(crate) fn expand_history<Err: Debug + Display>(
+    line: &str,
+    history: &dyn History,
+) -> Result<Option<String>, ReplError<Err>> {
+    if !line.trim_start().starts_with('!') {
+        return Ok(None);
+    }
+    let mut entries: Vec<_> = history_entries(history)?
+        .into_iter()
+        .map(|item| item.command_line)
+        .collect();
+    if entries.last().is_some_and(|entry| entry == line) {
+        entries.pop();
+    }
+    expand_designator(line, &entries).map_err(ReplError::EventNotFound)
+}
+
+/// Resolves the designator of `line` against `entries`, the history before it with the oldest
+/// entry first, returning the designator if no entry matches.
+///
+/// The history stores lines as they were typed, so a resolved entry that is a designator
+/// itself is expanded against the entries before it.
+fn expand_designator(line: &str, entries: &[String]) -> Result<Option<String>, String> {
+    let Some(designator) = line.trim_start().strip_prefix('!') else {
+        return Ok(None);
+    };
+    let (designator, rest) = designator
+        .split_once(char::is_whitespace)
+        .unwrap_or((designator, ""));
+    if designator.is_empty() {
+        return Ok(None);
+    }
+
+    let index = if designator == "!" {
+        entries.len().checked_sub(1)
+    } else if let Ok(index) = designator.parse::<usize>() {
+        index.checked_sub(1).filter(|index| *index < entries.len())
+    } else {
+        entries
+            .iter()
+            .rposition(|entry| entry.starts_with(designator))
+    };
+    let Some(index) = index else {
+        return Err(format!("!{designator}"));
+    };
+
+    let entry = &entries[index];
+    let entry = expand_designator(entry, &entries[..index])?.unwrap_or_else(|| entry.clone());
+    if rest.is_empty() {
+        Ok(Some(entry))
+    } else {
+        Ok(Some(format!("{entry} {rest}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn lines_without_designator_are_not_expanded() {
+        let entries = history(&["foo"]);
+        assert_eq!(expand_designator("foo", &entries), Ok(None));
+        assert_eq!(expand_designator("!", &entries), Ok(None));
+        assert_eq!(expand_designator("! foo", &entries), Ok(None));
+    }
+
+    #[test]
+    fn previous_command() {
+        let entries = history(&["foo", "bar"]);
+        assert_eq!(expand_designator("!!", &entries), Ok(Some("bar".into())));
+        assert_eq!(
+            expand_designator("  !! -v", &entries),
+            Ok(Some("bar -v".into()))
+        );
+        assert_eq!(expand_designator("!!", &[]), Err("!!".into()));
+    }
+
+    #[test]
+    fn entry_by_number() {
+        let entries = history(&["foo", "bar"]);
+        assert_eq!(expand_designator("!1", &entries), Ok(Some("foo".into())));
+        assert_eq!(
+            expand_designator("!2 x y", &entries),
+            Ok(Some("bar x y".into()))
+        );
+        assert_eq!(expand_designator("!0", &entries), Err("!0".into()));
+        assert_eq!(expand_designator("!3", &entries), Err("!3".into()));
+    }
+
+    #[test]
+    fn entry_by_prefix() {
+        let entries = history(&["foo 1", "bar", "foo 2"]);
+        assert_eq!(expand_designator("!fo", &entries), Ok(Some("foo 2".into())));
+        assert_eq!(expand_designator("!b", &entries), Ok(Some("bar".into())));
+        assert_eq!(expand_designator("!baz", &entries), Err("!baz".into()));
+    }
+
+    #[test]
+    fn designators_in_the_history_are_expanded() {
+        let entries = history(&["foo", "!f", "bar", "!2"]);
+        assert_eq!(expand_designator("!!", &entries), Ok(Some("foo".into())));
+        assert_eq!(
+            expand_designator("!4 x", &entries),
+            Ok(Some("foo x".into()))
+        );
+        assert_eq!(expand_designator("!2", &entries), Ok(Some("foo".into())));
+
+        let entries = history(&["foo a", "!! b", "!! c"]);
+        assert_eq!(
+            expand_designator("!!", &entries),
+            Ok(Some("foo a b c".into()))
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Once};
+
+/// The exit code used when a second Ctrl-C terminates the process, like a shell reports SIGINT
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Tokens of the commands that are currently running, the innermost one last
+static RUNNING: Mutex<Vec<CancellationToken>> = Mutex::new(Vec::new());
+
+/// The number of read loops that are running, Ctrl-C while no command runs only terminates the
+/// process during one
+static LOOPS: AtomicUsize = AtomicUsize::new(0);
+
+/// Signals that the running command should stop, e.g. because Ctrl-C was pressed.
+///
+/// Handlers can check [`CancellationToken::is_cancelled`] periodically or move a clone of the
+/// token into other threads. Futures of an `AsyncReplHandler` are dropped at their next await
+/// point once the token is cancelled.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    #[cfg(feature = "tokio")]
+    notify: tokio::sync::Notify,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        #[cfg(feature = "tokio")]
+        self.inner.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Completes once the token is cancelled
+    #[cfg(feature = "tokio")]
+    pub async fn cancelled(&self) {
+        let notified = self.inner.notify.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+        if self.is_cancelled() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+/// Registers `token` to be cancelled by [`interrupt`] until the returned guard is dropped.
+///
+/// If `install` is set, a process-wide Ctrl-C handler calling [`interrupt`] is installed the
+/// first time.
+pub(crate) fn watch(token: &CancellationToken, install: bool) -> WatchGuard {
+    static INSTALL: Once = Once::new();
+    if install {
+        INSTALL.call_once(|| {
+            // Fails if the application installed its own handler, which then stays responsible
+            let _ = ctrlc::set_handler(interrupt);
+        });
+    }
+    RUNNING
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .push(token.clone());
+    WatchGuard {
+        token: token.clone(),
+    }
+}
+
+/// Handles Ctrl-C for applications that install a handler of their own, see
+/// [`ReplContext::set_cancel_on_interrupt`](crate::ReplContext::set_cancel_on_interrupt).
+///
+/// The first call cancels all running commands and a second one while they still run terminates
+/// the process, also in script mode. A call while no command is running terminates the process
+/// if a read loop is running and does nothing otherwise. The editor reads Ctrl-C as a key while
+/// a prompt is shown.
+pub fn interrupt() {
+    let running = RUNNING.lock().unwrap_or_else(|err| err.into_inner());
+    if running.iter().any(CancellationToken::is_cancelled)
+        || (running.is_empty() && LOOPS.load(Ordering::SeqCst) > 0)
+    {
+        std::process::exit(INTERRUPTED_EXIT_CODE);
+    }
+    running.iter().for_each(CancellationToken::cancel);
+}
+
+/// Marks a read loop as running until the returned guard is dropped
+pub(crate) fn enter_loop() -> LoopGuard {
+    LOOPS.fetch_add(1, Ordering::SeqCst);
+    LoopGuard
+}
+
+pub(crate) struct LoopGuard;
+
+impl Drop for LoopGuard {
+    fn drop(&mut self) {
+        LOOPS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub(crate) struct WatchGuard {
+    token: CancellationToken,
+}
+
+impl Drop for WatchGuard {
+    fn drop(&mut self) {
+        let mut running = RUNNING.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(idx) = running
+            .iter()
+            .rposition(|token| Arc::ptr_eq(&token.inner, &self.token.inner))
+        {
+            running.remove(idx);
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use std::ffi::OsString;
+use std::fmt::{Debug, Display};
+
+use clap::{ArgMatches, Command};
+
+use crate::{ControlFlow, ErrorHandler, ExitStatus, ReplContext, ReplError};
+
+impl<C: clap::Parser + Debug, Err: Debug + Display> ReplContext<C, Err> {
+    /// Adds the arguments of `G` as options that can be passed to the process before or after
+    /// the command in [`ReplContext::run`].
+    ///
+    /// Their values are available to every command executed in either mode through
+    /// [`ExecutionContext::globals`](crate::ExecutionContext::globals).
+    pub fn set_global_args<G: clap::Args>(&mut self) {
+        self.global_args = G::augment_args(Command::new(""))
+            .get_arguments()
+            .map(|arg| arg.clone().global(true))
+            .collect();
+    }
+
+    /// Executes the command passed in the arguments of the process or starts
+    /// [`ReplContext::read_loop`] if there is none, see [`ReplContext::run_from`]
+    pub fn run<F: ErrorHandler<Err>>(self, handle_error: F) -> Result<ExitStatus, ReplError<Err>> {
+        self.run_from(std::env::args_os(), handle_error)
+    }
+
+    /// Parses `args` like a command line of the process, starting with the binary name.
+    ///
+    /// If they contain a command, it is executed once with its output written to stdout
+    /// directly. Errors are passed to `handle_error` and result in a failure status, clap
+    /// usage errors in the status 2 clap itself uses. Otherwise [`ReplContext::read_loop`] is
+    /// started with the global options set by [`ReplContext::set_global_args`] kept for all
+    /// commands.
+    pub fn run_from<F: ErrorHandler<Err>>(
+        mut self,
+        args: impl IntoIterator<Item = impl Into<OsString> + Clone>,
+        handle_error: F,
+    ) -> Result<ExitStatus, ReplError<Err>> {
+        let mut command = C::command()
+            .subcommand_required(false)
+            .arg_required_else_help(false)
+            .args(self.global_args.iter().cloned());
+        let matches = match command.try_get_matches_from_mut(args) {
+            Ok(matches) => matches,
+            Err(err) => {
+                let status = if err.use_stderr() {
+                    ExitStatus(2)
+                } else {
+                    ExitStatus::SUCCESS
+                };
+                let mut context = self.error_context(&mut command, None);
+                handle_error.handle(&mut context, ReplError::Parse(err))?;
+                return Ok(status);
+            }
+        };
+        self.global_matches = global_matches(&matches);
+        if matches.subcommand().is_none() {
+            return self.read_loop(handle_error);
+        }
+
+        self.interactive = false;
+        let result = self.execute_guarded(&mut command, |context, command| {
+            context.execute_matches(command, &matches)
+        });
+        match result {
+            Ok(ControlFlow::Exit(status)) => Ok(status),
+            Ok(_) => Ok(ExitStatus::SUCCESS),
+            Err(err) => {
+                let mut context = self.error_context(&mut command, None);
+                handle_error.handle(&mut context, err)?;
+                Ok(ExitStatus(1))
+            }
+        }
+    }
+}
+
+/// The values of the root arguments. clap also stores global arguments given after the
+/// subcommand there.
+fn global_matches(matches: &ArgMatches) -> ArgMatches {
+    let mut matches = matches.clone();
+    matches.remove_subcommand();
+    matches
+}
@@ -0,0 +1,525 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use clap::{Arg, ArgMatches, Command, ValueHint};
+use reedline::{Completer, Span, Suggestion};
+
+use crate::analyze::{analyze, find_long, Analysis};
+use crate::{Token, Tokenizer};
+
+/// Name of the completion menu that [`TermReader`](crate::TermReader) registers
+pub const COMPLETION_MENU: &str = "completion_menu";
+
+/// A completion candidate for the value of an argument
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub value: String,
+    pub description: Option<String>,
+    /// Whether a space is inserted after the value once it is accepted
+    pub append_whitespace: bool,
+}
+
+impl Candidate {
+    pub fn new(value: impl Into<String>, description: Option<impl ToString>) -> Self {
+        Self {
+            value: value.into(),
+            description: description.map(|description| description.to_string()),
+            append_whitespace: true,
+        }
+    }
+}
+
+impl<S: Into<String>> From<S> for Candidate {
+    fn from(value: S) -> Self {
+        Self::new(value, None::<String>)
+    }
+}
+
+/// Information about the argument value that is being completed
+pub struct CompletionContext<'a> {
+    /// The root command the line is parsed with
+    pub root: &'a Command,
+    /// The (sub)command the argument belongs to
+    pub command: &'a Command,
+    /// The argument whose value is being completed
+    pub arg: &'a Arg,
+    /// The part of the value that was already typed
+    pub prefix: &'a str,
+    /// The arguments typed before the value that is being completed
+    pub args: &'a [String],
+}
+
+impl<'a> CompletionContext<'a> {
+    /// Parses the already typed arguments and returns the matches of the innermost subcommand.
+    ///
+    /// Errors like missing required arguments are ignored, so the matches only contain
+    /// what could be parsed so far.
+    pub fn matches(&self) -> Option<ArgMatches> {
+        let matches = self
+            .root
+            .clone()
+            .ignore_errors(true)
+            .try_get_matches_from(self.args)
+            .ok()?;
+        let mut matches = &matches;
+        while let Some((_, subcommand)) = matches.subcommand() {
+            matches = subcommand;
+        }
+        Some(matches.clone())
+    }
+}
+
+/// Supplies completion candidates for argument values at runtime
+pub trait ValueCompleter: Send + Sync {
+    fn complete(&self, ctx: &CompletionContext) -> Vec<Candidate>;
+}
+
+impl<F: Fn(&CompletionContext) -> Vec<Candidate> + Send + Sync> ValueCompleter for F {
+    fn complete(&self, ctx: &CompletionContext) -> Vec<Candidate> {
+        self(ctx)
+    }
+}
+
+/// The [`ValueCompleter`]s registered for argument ids, with an optional fallback for all
+/// other arguments
+#[derive(Clone, Default)]
+pub struct ValueCompleters {
+    by_arg: HashMap<String, Arc<dyn ValueCompleter>>,
+    fallback: Option<Arc<dyn ValueCompleter>>,
+}
+
+impl ValueCompleters {
+    pub fn register(&mut self, arg: impl Into<String>, completer: impl ValueCompleter + 'static) {
+        self.by_arg.insert(arg.into(), Arc::new(completer));
+    }
+
+    pub fn set_fallback(&mut self, completer: impl ValueCompleter + 'static) {
+        self.fallback = Some(Arc::new(completer));
+    }
+
+    pub fn get(&self, arg: &Arg) -> Option<&dyn ValueCompleter> {
+        self.by_arg
+            .get(arg.get_id().as_str())
+            .or(self.fallback.as_ref())
+            .map(|completer| &**completer)
+    }
+}
+
+/// A [`Completer`] that completes subcommands, flags and values using the clap [`Command`] tree
+pub struct ReplCompleter {
+    command: Command,
+    tokenizer: Arc<dyn Tokenizer + Send + Sync>,
+    value_completers: ValueCompleters,
+}
+
+impl ReplCompleter {
+    pub fn new(command: Command, tokenizer: Arc<dyn Tokenizer + Send + Sync>) -> Self {
+        Self {
+            command,
+            tokenizer,
+            value_completers: ValueCompleters::default(),
+        }
+    }
+
+    pub fn with_value_completers(mut self, value_completers: ValueCompleters) -> Self {
+        self.value_completers = value_completers;
+        self
+    }
+
+    fn value_candidates(
+        &self,
+        analysis: &Analysis,
+        arg: &Arg,
+        args: &[String],
+        prefix: &str,
+        candidates: &mut Vec<Candidate>,
+    ) {
+        static_value_candidates(arg, candidates);
+        match arg.get_value_hint() {
+            ValueHint::AnyPath | ValueHint::FilePath => path_candidates(prefix, false, candidates),
+            ValueHint::DirPath => path_candidates(prefix, true, candidates),
+            _ => {}
+        }
+        if let Some(completer) = self.value_completers.get(arg) {
+            candidates.extend(completer.complete(&CompletionContext {
+                root: &self.command,
+                command: analysis.command(),
+                arg,
+                prefix,
+                args,
+            }));
+        }
+    }
+}
+
+impl Completer for ReplCompleter {
+    fn complete(&mut self, line: &str, pos: usize) -> Vec<Suggestion> {
+        let mut tokens = self.tokenizer.tokenize_incomplete(&line[..pos]);
+        let (mut prefix, mut start) = match tokens.last() {
+            Some(token) if token.span.end == pos => {
+                let token = tokens.pop().unwrap();
+                (token.value, token.span.start)
+            }
+            _ => (String::new(), pos),
+        };
+
+        let analysis = analyze(&self.command, &tokens);
+        if !analysis.valid {
+            return Vec::new();
+        }
+        let args: Vec<_> = tokens.into_iter().map(|token| token.value).collect();
+
+        let mut candidates = Vec::new();
+        if let Some(arg) = analysis.pending {
+            self.value_candidates(&analysis, arg, &args, &prefix, &mut candidates);
+        } else if !analysis.only_positionals && prefix.starts_with('-') {
+            let inline_value = prefix
+                .strip_prefix("--")
+                .and_then(|long| long.split_once('='))
+                .map(|(name, value)| (name.to_string(), value.to_string()));
+            match inline_value {
+                Some((name, value)) => {
+                    start += line[start..pos].find('=').map_or(0, |idx| idx + 1);
+                    prefix = value;
+                    if let Some(arg) = find_long(analysis.command(), &name) {
+                        self.value_candidates(&analysis, arg, &args, &prefix, &mut candidates);
+                    }
+                }
+                None => flag_candidates(&analysis, &prefix, &mut candidates),
+            }
+        } else {
+            if analysis.expects_subcommand() {
+                subcommand_candidates(analysis.command(), &mut candidates);
+            }
+            if let Some(arg) = analysis.next_positional() {
+                self.value_candidates(&analysis, arg, &args, &prefix, &mut candidates);
+            }
+        }
+
+        candidates
+            .into_iter()
+            .filter(|candidate| candidate.value.starts_with(&prefix))
+            .map(|candidate| Suggestion {
+                value: quote_candidate(&*self.tokenizer, &candidate.value),
+                description: candidate.description,
+                extra: None,
+                span: Span::new(start, pos),
+                append_whitespace: candidate.append_whitespace,
+            })
+            .collect()
+    }
+}
+
+fn subcommand_candidates(command: &Command, candidates: &mut Vec<Candidate>) {
+    candidates.extend(
+        command
+            .get_subcommands()
+            .filter(|subcommand| !subcommand.is_hide_set())
+            .map(|subcommand| Candidate::new(subcommand.get_name(), subcommand.get_about())),
+    );
+}
+
+fn flag_candidates(analysis: &Analysis, prefix: &str, candidates: &mut Vec<Candidate>) {
+    let flags = analysis
+        .command()
+        .get_arguments()
+        .filter(|arg| !arg.is_positional() && !arg.is_hide_set() && analysis.is_available(arg));
+    for arg in flags {
+        if let Some(long) = arg.get_long() {
+            candidates.push(Candidate::new(format!("--{long}"), arg.get_help()));
+        }
+        if let Some(short) = arg.get_short().filter(|_| !prefix.starts_with("--")) {
+            candidates.push(Candidate::new(format!("-{short}"), arg.get_help()));
+        }
+    }
+}
+
+fn static_value_candidates(arg: &Arg, candidates: &mut Vec<Candidate>) {
+    candidates.extend(
+        arg.get_possible_values()
+            .into_iter()
+            .filter(|value| !value.is_hide_set())
+            .map(|value| Candidate::new(value.get_name(), value.get_help())),
+    );
+}
+
+fn is_path(arg: &Arg) -> bool {
+    matches!(
+        arg.get_value_hint(),
+        ValueHint::AnyPath | ValueHint::FilePath | ValueHint::DirPath
+    )
+}
+
+fn is_separator(ch: char) -> bool {
+    ch == '/' || std::path::is_separator(ch)
+}
+
+/// Whether `raw` starts with a `~` that refers to the home directory
+fn is_home_prefix(raw: &str) -> bool {
+    match raw.strip_prefix('~') {
+        Some(rest) => rest.is_empty() || rest.starts_with(is_separator),
+        None => false,
+    }
+}
+
+/// Quotes `value` with `tokenizer`, keeping a leading `~/` unquoted so that it still refers to the
+/// home directory
+fn quote_candidate(tokenizer: &dyn Tokenizer, value: &str) -> String {
+    match value.strip_prefix('~') {
+        Some(rest) if rest.starts_with(is_separator) => {
+            let (separator, rest) = rest.split_at(1);
+            if rest.is_empty() {
+                value.to_string()
+            } else {
+                format!("~{separator}{}", tokenizer.quote(rest))
+            }
+        }
+        _ => tokenizer.quote(value),
+    }
+}
+
+/// Replaces the `~` at the start of values of path-hinted arguments with the home directory,
+/// unless it was quoted or escaped in `line`
+pub(crate) fn expand_home(command: &Command, line: &str, tokens: &mut [Token]) {
+    let Some(home) = dirs::home_dir() else {
+        return;
+    };
+    let analysis = analyze(command, tokens);
+    for (token, arg) in tokens.iter_mut().zip(analysis.args) {
+        let raw = line.get(token.span.clone()).unwrap_or_default();
+        if arg.is_some_and(is_path) && is_home_prefix(raw) && token.value.starts_with('~') {
+            token.value.replace_range(..1, &home.to_string_lossy());
+        }
+    }
+}
+
+/// Completes the files and directories starting with `prefix`, relative to the current directory
+fn path_candidates(prefix: &str, dirs_only: bool, candidates: &mut Vec<Candidate>) {
+    let (dir, name) = match prefix.rfind(is_separator) {
+        Some(idx) => prefix.split_at(idx + 1),
+        None if prefix == "~" => ("~/", ""),
+        None => ("", prefix),
+    };
+
+    let search_dir = match dir
+        .strip_prefix('~')
+        .filter(|rest| rest.starts_with(is_separator))
+    {
+        Some(rest) => match dirs::home_dir() {
+            Some(home) => home.join(&rest[1..]),
+            None => return,
+        },
+        None if dir.is_empty() => PathBuf::from("."),
+        None => PathBuf::from(dir),
+    };
+    let Ok(entries) = std::fs::read_dir(&search_dir) else {
+        return;
+    };
+
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let file_name = entry.file_name().into_string().ok()?;
+            if !file_name.starts_with(name)
+                || (file_name.starts_with('.') && !name.starts_with('.'))
+            {
+                return None;
+            }
+            let is_dir = Path::is_dir(&entry.path());
+            (is_dir || !dirs_only).then_some((file_name, is_dir))
+        })
+        .collect();
+    paths.sort();
+
+    candidates.extend(paths.into_iter().map(|(file_name, is_dir)| Candidate {
+        value: if is_dir {
+            format!("{dir}{file_name}/")
+        } else {
+            format!("{dir}{file_name}")
+        },
+        description: None,
+        append_whitespace: !is_dir,
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ShellTokenizer;
+
+    #[derive(Debug, Clone, clap::ValueEnum)]
+    enum Format {
+        /// One JSON object per line
+        Json,
+        Plain,
+    }
+
+    fn command() -> Command {
+        let mut command = Command::new("repl")
+            .multicall(true)
+            .subcommand(
+                Command::new("load")
+                    .arg(Arg::new("file").value_hint(ValueHint::FilePath))
+                    .arg(
+                        Arg::new("dir")
+                            .short('d')
+                            .long("dir")
+                            .value_hint(ValueHint::DirPath),
+                    )
+                    .arg(
+                        Arg::new("format")
+                            .long("format")
+                            .value_parser(clap::value_parser!(Format)),
+                    ),
+            )
+            .subcommand(Command::new("note").arg(Arg::new("text")));
+        command.build();
+        command
+    }
+
+    fn expanded(line: &str) -> Vec<String> {
+        let mut tokens = ShellTokenizer.tokenize(line).unwrap();
+        expand_home(&command(), line, &mut tokens);
+        tokens.into_iter().map(|token| token.value).collect()
+    }
+
+    fn complete(completer: &mut ReplCompleter, line: &str) -> Vec<String> {
+        let suggestions = completer.complete(line, line.len());
+        suggestions
+            .into_iter()
+            .map(|suggestion| suggestion.value)
+            .collect()
+    }
+
+    #[test]
+    fn subcommands_and_flags_are_completed() {
+        let mut completer = ReplCompleter::new(command(), Arc::new(ShellTokenizer));
+        assert_eq!(complete(&mut completer, ""), ["load", "note", "help"]);
+        assert_eq!(complete(&mut completer, "lo"), ["load"]);
+        assert_eq!(
+            complete(&mut completer, "load --"),
+            ["--dir", "--format", "--help"]
+        );
+        assert_eq!(
+            complete(&mut completer, "load -d x --"),
+            ["--format", "--help"]
+        );
+        assert!(complete(&mut completer, "unknown ").is_empty());
+    }
+
+    #[test]
+    fn value_enum_values_are_completed() {
+        let mut completer = ReplCompleter::new(command(), Arc::new(ShellTokenizer));
+        assert_eq!(
+            complete(&mut completer, "load --format "),
+            ["json", "plain"]
+        );
+        assert_eq!(complete(&mut completer, "load --format=p"), ["plain"]);
+
+        let suggestions = completer.complete("load --format=j", 15);
+        assert_eq!(suggestions[0].span, Span::new(14, 15));
+        assert_eq!(
+            suggestions[0].description.as_deref(),
+            Some("One JSON object per line")
+        );
+    }
+
+    #[test]
+    fn registered_completer_is_used() {
+        let mut completers = ValueCompleters::default();
+        completers.register("text", |ctx: &CompletionContext| {
+            assert_eq!(ctx.command.get_name(), "note");
+            assert_eq!(ctx.args, ["note"]);
+            vec![
+                Candidate::new("hello", Some("greeting")),
+                "hello world".into(),
+            ]
+        });
+        let mut completer = ReplCompleter::new(command(), Arc::new(ShellTokenizer))
+            .with_value_completers(completers);
+        assert_eq!(
+            complete(&mut completer, "note h"),
+            ["hello", "'hello world'"]
+        );
+        assert!(complete(&mut completer, "note x").is_empty());
+    }
+
+    #[test]
+    fn directories_are_completed() {
+        let dir = std::env::temp_dir().join(format!("repellet-complete-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("my dir")).unwrap();
+        std::fs::create_dir_all(dir.join("other")).unwrap();
+        std::fs::write(dir.join("file.txt"), "").unwrap();
+        let mut completer = ReplCompleter::new(command(), Arc::new(ShellTokenizer));
+        let prefix = format!("{}/", dir.display());
+
+        let dirs = complete(&mut completer, &format!("load -d {prefix}"));
+        let all = complete(&mut completer, &format!("load {prefix}"));
+        let quoted = complete(&mut completer, &format!("load -d '{prefix}my"));
+        let suggestions = completer.complete(&format!("load -d {prefix}o"), prefix.len() + 9);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            dirs,
+            [format!("'{prefix}my dir/'"), format!("{prefix}other/")]
+        );
+        assert_eq!(
+            all,
+            [
+                format!("{prefix}file.txt"),
+                format!("'{prefix}my dir/'"),
+                format!("{prefix}other/")
+            ]
+        );
+        assert_eq!(quoted, [format!("'{prefix}my dir/'")]);
+        assert!(!suggestions[0].append_whitespace);
+    }
+
+    #[test]
+    fn leading_tilde_stays_unquoted() {
+        let mut completers = ValueCompleters::default();
+        completers.register("text", |_: &CompletionContext| {
+            vec!["~/my dir/".into(), "~/plain".into(), "~".into()]
+        });
+        let mut completer = ReplCompleter::new(command(), Arc::new(ShellTokenizer))
+            .with_value_completers(completers);
+
+        assert_eq!(
+            complete(&mut completer, "note ~/"),
+            ["~/'my dir/'", "~/plain"]
+        );
+        assert_eq!(complete(&mut completer, "note ~/'my"), ["~/'my dir/'"]);
+        assert_eq!(
+            complete(&mut completer, "note ~"),
+            ["~/'my dir/'", "~/plain", "'~'"]
+        );
+
+        let home = dirs::home_dir().unwrap().to_string_lossy().into_owned();
+        assert_eq!(
+            expanded("load ~/'my dir/'"),
+            ["load", format!("{home}/my dir/").as_str()]
+        );
+    }
+
+    #[test]
+    fn leading_tilde_of_paths_is_expanded() {
+        let home = dirs::home_dir().unwrap().to_string_lossy().into_owned();
+        assert_eq!(expanded("load ~"), ["load", home.as_str()]);
+        assert_eq!(
+            expanded("load ~/a.repl -d ~/'my dir'"),
+            [
+                "load",
+                format!("{home}/a.repl").as_str(),
+                "-d",
+                format!("{home}/my dir").as_str()
+            ]
+        );
+        assert_eq!(
+            expanded("load '~/a' \\~ --dir=~/b"),
+            ["load", "~/a", "~", "--dir=~/b"]
+        );
+        assert_eq!(expanded("load ~user"), ["load", "~user"]);
+        assert_eq!(expanded("note ~"), ["note", "~"]);
+    }
+}
@@ -0,0 +1,96 @@
+use std::fmt::{Debug, Display};
+
+use nu_ansi_term::{Color, Style};
+
+use crate::{ErrorContext, ErrorHandler, Printer, ReplError, ReplExecutionError, ScriptLocation};
+
+/// An error handler that prints errors through a [`Printer`] and needs no logging backend.
+///
+/// Clap errors, including help and version output, are printed the way clap prints them.
+/// Errors returned by the handler are printed in a configurable style and panics with their
+/// message, after which the loop continues. [`ReplError::Interrupt`], [`ReplError::EOF`] and
+/// [`ReplError::Io`] end the loop, like with the `default_error_handler`.
+#[derive(Debug, Clone)]
+pub struct ErrorPrinter {
+    printer: Printer,
+    style: Style,
+}
+
+impl ErrorPrinter {
+    pub fn new(printer: Printer) -> Self {
+        Self {
+            printer,
+            style: Color::Red.normal(),
+        }
+    }
+
+    /// Sets the style of errors returned by the handler, red by default
+    pub fn with_style(mut self, style: Style) -> Self {
+        self.style = style;
+        self
+    }
+
+    fn print<Err: Debug + Display>(
+        &self,
+        location: Option<&ScriptLocation>,
+        error: &ReplError<Err>,
+    ) {
+        let prefix = location
+            .map(|location| format!("{location}: "))
+            .unwrap_or_default();
+        match error {
+            ReplError::Clap(err) => self.print_clap(&prefix, err),
+            ReplError::Parse(err) => self.print_clap(&prefix, err),
+            ReplError::ExecutionError(err) => {
+                let message = self.printer.paint(self.style, err);
+                self.printer.error(format!("{prefix}{message}"))
+            }
+            ReplError::Panic(report) => match report.backtrace() {
+                Some(backtrace) => self
+                    .printer
+                    .error(format!("{prefix}command {report}\n{backtrace}")),
+                None => self.printer.error(format!("{prefix}command {report}")),
+            },
+            error => self.printer.error(format!("{prefix}{error}")),
+        }
+    }
+
+    fn print_clap<F: clap::error::ErrorFormatter>(
+        &self,
+        prefix: &str,
+        err: &clap::error::Error<F>,
+    ) {
+        let rendered = if self.printer.is_colored() {
+            err.render().ansi().to_string()
+        } else {
+            err.render().to_string()
+        };
+        self.printer
+            .print(format!("{prefix}{}", rendered.trim_end()));
+    }
+}
+
+impl<Err: ReplExecutionError> ErrorHandler<Err> for ErrorPrinter {
+    fn handle(
+        &self,
+        _ctx: &mut ErrorContext<'_>,
+        error: ReplError<Err>,
+    ) -> Result<(), ReplError<Err>> {
+        match error {
+            ReplError::Interrupt | ReplError::EOF | ReplError::Io(_) => Err(error),
+            ReplError::Script { location, error } => match *error {
+                ReplError::Interrupt | ReplError::EOF | ReplError::Io(_) => {
+                    Err(ReplError::Script { location, error })
+                }
+                ref inner => {
+                    self.print(Some(&location), inner);
+                    Ok(())
+                }
+            },
+            error => {
+                self.print(None, &error);
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use std::sync::Arc;
+
+use clap::Command;
+use nu_ansi_term::{Color, Style};
+use reedline::{Highlighter, StyledText};
+
+use crate::analyze::{analyze, TokenKind};
+use crate::Tokenizer;
+
+/// The styles used by the [`ReplHighlighter`] and [`ReplHinter`](crate::ReplHinter)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HighlightStyles {
+    pub subcommand: Style,
+    pub unknown_subcommand: Style,
+    pub flag: Style,
+    pub unknown_flag: Style,
+    pub value: Style,
+    pub quoted: Style,
+    /// Positional values the command does not accept
+    pub unexpected: Style,
+    /// Whitespace and everything following an unknown subcommand
+    pub default: Style,
+    pub hint: Style,
+}
+
+impl Default for HighlightStyles {
+    fn default() -> Self {
+        Self {
+            subcommand: Color::Green.bold(),
+            unknown_subcommand: Color::Red.normal(),
+            flag: Color::Cyan.normal(),
+            unknown_flag: Color::Red.underline(),
+            value: Style::new(),
+            quoted: Color::Yellow.normal(),
+            unexpected: Color::Red.normal(),
+            default: Style::new(),
+            hint: Color::DarkGray.normal(),
+        }
+    }
+}
+
+/// A [`Highlighter`] that colors command lines based on the clap [`Command`] tree
+pub struct ReplHighlighter {
+    command: Command,
+    tokenizer: Arc<dyn Tokenizer + Send + Sync>,
+    styles: HighlightStyles,
+}
+
+impl ReplHighlighter {
+    pub fn new(command: Command, tokenizer: Arc<dyn Tokenizer + Send + Sync>) -> Self {
+        Self {
+            command,
+            tokenizer,
+            styles: HighlightStyles::default(),
+        }
+    }
+
+    pub fn with_styles(mut self, styles: HighlightStyles) -> Self {
+        self.styles = styles;
+        self
+    }
+
+    fn style_of(&self, kind: TokenKind, raw: &str) -> Style {
+        let styles = &self.styles;
+        match kind {
+            TokenKind::Subcommand => styles.subcommand,
+            TokenKind::UnknownSubcommand => styles.unknown_subcommand,
+            TokenKind::Flag | TokenKind::Escape => styles.flag,
+            TokenKind::UnknownFlag => styles.unknown_flag,
+            TokenKind::FlagValue | TokenKind::Positional => {
+                if raw.starts_with(['\'', '"']) {
+                    styles.quoted
+                } else {
+                    styles.value
+                }
+            }
+            TokenKind::UnexpectedPositional => styles.unexpected,
+            TokenKind::Invalid => styles.default,
+        }
+    }
+}
+
+impl Highlighter for ReplHighlighter {
+    fn highlight(&self, line: &str, _cursor: usize) -> StyledText {
+        let tokens = self.tokenizer.tokenize_incomplete(line);
+        let analysis = analyze(&self.command, &tokens);
+
+        let mut styled = StyledText::new();
+        let mut end = 0;
+        for (token, kind) in tokens.iter().zip(analysis.kinds) {
+            if token.span.start > end {
+                styled.push((self.styles.default, line[end..token.span.start].to_string()));
+            }
+            let raw = &line[token.span.clone()];
+            styled.push((self.style_of(kind, raw), raw.to_string()));
+            end = token.span.end;
+        }
+        if end < line.len() {
+            styled.push((self.styles.default, line[end..].to_string()));
+        }
+        styled
+    }
+}
@@ -0,0 +1,180 @@
+use std::sync::Arc;
+
+use clap::{Arg, Command};
+use nu_ansi_term::Style;
+use reedline::{DefaultHinter, Hinter, History};
+
+use crate::analyze::{analyze, Analysis, TokenKind};
+use crate::Tokenizer;
+
+/// A [`Hinter`] that shows the arguments a command still requires or the help of the
+/// command that was just typed, falling back to hints from the history
+pub struct ReplHinter {
+    command: Command,
+    tokenizer: Arc<dyn Tokenizer + Send + Sync>,
+    style: Style,
+    history: DefaultHinter,
+    /// Whether the current hint was derived from the command tree instead of the history
+    structural: bool,
+}
+
+impl ReplHinter {
+    pub fn new(command: Command, tokenizer: Arc<dyn Tokenizer + Send + Sync>) -> Self {
+        Self {
+            command,
+            tokenizer,
+            style: Style::default(),
+            history: DefaultHinter::default(),
+            structural: false,
+        }
+    }
+
+    pub fn with_style(mut self, style: Style) -> Self {
+        self.style = style;
+        self.history = DefaultHinter::default().with_style(style);
+        self
+    }
+
+    fn structural_hint(&self, line: &str) -> Option<String> {
+        let tokens = self.tokenizer.tokenize_incomplete(line);
+        let last = tokens.last()?;
+        let at_boundary = last.span.end < line.len();
+        let analysis = analyze(&self.command, &tokens);
+        let last_kind = *analysis.kinds.last()?;
+        if !analysis.valid || !(at_boundary || last_kind == TokenKind::Subcommand) {
+            return None;
+        }
+
+        let hint = match analysis.pending {
+            Some(arg) => value_name(arg),
+            None => required_positionals(&analysis)
+                .or_else(|| {
+                    (last_kind == TokenKind::Subcommand)
+                        .then(|| analysis.command().get_about())
+                        .flatten()
+                        .map(|about| about.to_string())
+                })
+                .filter(|hint| !hint.is_empty())?,
+        };
+        Some(if at_boundary {
+            hint
+        } else {
+            format!(" {hint}")
+        })
+    }
+}
+
+fn value_name(arg: &Arg) -> String {
+    let name = match arg.get_value_names() {
+        Some([name, ..]) => name.to_string(),
+        _ => arg.get_id().to_string().to_uppercase(),
+    };
+    let multiple = arg
+        .get_num_args()
+        .map(|range| range.max_values() > 1)
+        .unwrap_or(false);
+    if multiple {
+        format!("<{name}>...")
+    } else {
+        format!("<{name}>")
+    }
+}
+
+fn required_positionals(analysis: &Analysis) -> Option<String> {
+    let required: Vec<_> = analysis
+        .command()
+        .get_positionals()
+        .skip(analysis.positionals)
+        .filter(|arg| arg.is_required_set())
+        .map(value_name)
+        .collect();
+    (!required.is_empty()).then(|| required.join(" "))
+}
+
+impl Hinter for ReplHinter {
+    fn handle(
+        &mut self,
+        line: &str,
+        pos: usize,
+        history: &dyn History,
+        use_ansi_coloring: bool,
+    ) -> String {
+        let hint = (pos == line.len())
+            .then(|| self.structural_hint(line))
+            .flatten();
+        self.structural = hint.is_some();
+        match hint {
+            Some(hint) if use_ansi_coloring => self.style.paint(hint).to_string(),
+            Some(hint) => hint,
+            None => self.history.handle(line, pos, history, use_ansi_coloring),
+        }
+    }
+
+    fn complete_hint(&self) -> String {
+        if self.structural {
+            String::new()
+        } else {
+            self.history.complete_hint()
+        }
+    }
+
+    fn next_hint_token(&self) -> String {
+        if self.structural {
+            String::new()
+        } else {
+            self.history.next_hint_token()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ShellTokenizer;
+    use reedline::FileBackedHistory;
+
+    fn hinter() -> ReplHinter {
+        let mut command = Command::new("repl")
+            .multicall(true)
+            .subcommand(
+                Command::new("connect")
+                    .about("Connects to a server")
+                    .arg(Arg::new("host").required(true))
+                    .arg(Arg::new("port").required(true))
+                    .arg(Arg::new("protocol").short('p').value_name("PROTO")),
+            )
+            .subcommand(
+                Command::new("tag")
+                    .about("Tags the connection")
+                    .arg(Arg::new("tags").required(true).num_args(1..)),
+            )
+            .subcommand(Command::new("status").about("Shows the connection"));
+        command.build();
+        ReplHinter::new(command, Arc::new(ShellTokenizer))
+    }
+
+    fn hint(hinter: &mut ReplHinter, line: &str) -> String {
+        let history = FileBackedHistory::default();
+        hinter.handle(line, line.len(), &history, false)
+    }
+
+    #[test]
+    fn required_positionals_are_hinted() {
+        let mut hinter = hinter();
+        assert_eq!(hint(&mut hinter, "connect "), "<HOST> <PORT>");
+        assert_eq!(hint(&mut hinter, "connect"), " <HOST> <PORT>");
+        assert_eq!(hint(&mut hinter, "connect localhost "), "<PORT>");
+        assert_eq!(hint(&mut hinter, "connect -p "), "<PROTO>");
+        assert_eq!(hint(&mut hinter, "tag "), "<TAGS>...");
+        assert!(hinter.complete_hint().is_empty());
+    }
+
+    #[test]
+    fn about_is_hinted_without_required_args() {
+        let mut hinter = hinter();
+        assert_eq!(hint(&mut hinter, "status"), " Shows the connection");
+        assert_eq!(hint(&mut hinter, "status "), "Shows the connection");
+        assert_eq!(hint(&mut hinter, "connect a 1 "), "");
+        assert_eq!(hint(&mut hinter, "unknown "), "");
+    }
+}
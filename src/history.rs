@@ -0,0 +1,209 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use reedline::{
+    CommandLineSearch, FileBackedHistory, History, Reedline, SearchDirection, SearchFilter,
+    SearchQuery,
+};
+
+/// The storage used for a persistent history
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HistoryBackend {
+    /// A plain text file with one command per line
+    #[default]
+    File,
+    /// A SQLite database that also stores timestamps, durations and exit statuses
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+}
+
+/// Configuration of a persistent history for [`TermReader::with_history`](crate::TermReader::with_history)
+#[derive(Debug, Clone)]
+pub struct HistoryConfig {
+    /// Name of the directory inside the platform data directory used if no path is set
+    pub name: String,
+    /// Path of the history file
+    pub path: Option<PathBuf>,
+    /// Maximum number of entries kept, only supported by the file backend
+    pub max_size: usize,
+    /// Removes earlier entries with the same command line when a command is saved.
+    ///
+    /// The file backend can not delete entries, it removes the duplicates from the file when the
+    /// history is loaded and only skips consecutive duplicates while the REPL runs.
+    pub dedup: bool,
+    pub backend: HistoryBackend,
+}
+
+impl HistoryConfig {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            path: None,
+            max_size: 1000,
+            dedup: false,
+            backend: HistoryBackend::default(),
+        }
+    }
+
+    /// Creates a config that stores the history in a directory named after the clap command
+    pub fn for_command<C: clap::CommandFactory>() -> Self {
+        Self::new(C::command().get_name())
+    }
+
+    pub fn with_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    pub fn with_dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    pub fn with_backend(mut self, backend: HistoryBackend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// The path of the history file, defaulting to `<data dir>/<name>/history.txt` or
+    /// `<data dir>/<name>/history.sqlite3` depending on the backend
+    pub fn resolve_path(&self) -> std::io::Result<PathBuf> {
+        if let Some(path) = &self.path {
+            return Ok(path.clone());
+        }
+        let file_name = match self.backend {
+            HistoryBackend::File => "history.txt",
+            #[cfg(feature = "sqlite")]
+            HistoryBackend::Sqlite => "history.sqlite3",
+        };
+        dirs::data_dir()
+            .map(|dir| dir.join(&self.name).join(file_name))
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    "Could not determine the data directory",
+                )
+            })
+    }
+}
+
+/// Deletes all but the most recent entry with the command line `line`
+pub(crate) fn remove_duplicates(editor: &mut Reedline, line: &str) {
+    let mut query = SearchQuery::everything(SearchDirection::Backward, None);
+    query.filter = SearchFilter::from_text_search(CommandLineSearch::Exact(line.to_string()), None);
+    let history = editor.history_mut();
+    let duplicates = history.search(query).unwrap_or_default();
+    for id in duplicates.into_iter().skip(1).filter_map(|item| item.id) {
+        // Not every backend supports deleting entries
+        let _ = history.delete(id);
+    }
+}
+
+/// Replaces the history of `editor` with the one described by `config`
+pub(crate) fn install(editor: Reedline, config: &HistoryConfig) -> std::io::Result<Reedline> {
+    let path = config.resolve_path()?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let (history, editor): (Box<dyn History>, _) = match config.backend {
+        HistoryBackend::File => {
+            if config.dedup {
+                compact_file(&path)?;
+            }
+            (
+                Box::new(FileBackedHistory::with_file(config.max_size, path)?),
+                editor,
+            )
+        }
+        #[cfg(feature = "sqlite")]
+        HistoryBackend::Sqlite => {
+            let session = Reedline::create_history_session_id();
+            let history =
+                reedline::SqliteBackedHistory::with_file(path, session, Some(chrono::Utc::now()))
+                    .map_err(std::io::Error::other)?;
+            (Box::new(history), editor.with_history_session_id(session))
+        }
+    };
+    Ok(editor.with_history(history))
+}
+
+/// Rewrites the history file at `path` keeping only the most recent entry of each command line
+fn compact_file(path: &Path) -> std::io::Result<()> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    let entries: Vec<&str> = contents.lines().collect();
+    let mut seen = HashSet::new();
+    let mut kept: Vec<&str> = entries
+        .iter()
+        .rev()
+        .copied()
+        .filter(|entry| seen.insert(*entry))
+        .collect();
+    if kept.len() == entries.len() {
+        return Ok(());
+    }
+    kept.reverse();
+    let mut compacted = kept.join("\n");
+    compacted.push('\n');
+    std::fs::write(path, compacted)
+}
+
+/// When a command started executing, used to store its duration and exit status in the history
+#[cfg(feature = "sqlite")]
+pub(crate) struct CommandStart {
+    instant: std::time::Instant,
+    timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+#[cfg(feature = "sqlite")]
+impl CommandStart {
+    pub fn now() -> Self {
+        Self {
+            instant: std::time::Instant::now(),
+            timestamp: chrono::Utc::now(),
+        }
+    }
+
+    pub fn record(self, editor: &mut Reedline, success: bool) {
+        if !editor.has_last_command_context() {
+            return;
+        }
+        let duration = self.instant.elapsed();
+        let cwd = std::env::current_dir()
+            .ok()
+            .map(|cwd| cwd.to_string_lossy().into_owned());
+        // The file backend does not support updating entries
+        let _ = editor.update_last_command_context(&|mut item| {
+            item.start_timestamp = Some(self.timestamp);
+            item.duration = Some(duration);
+            item.exit_status = Some(if success { 0 } else { 1 });
+            item.cwd = cwd.clone();
+            item
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_is_compacted_on_load() {
+        let path = std::env::temp_dir().join(format!("repellet-history-{}", std::process::id()));
+        std::fs::write(&path, "a\nb\na\nc\nb\n").unwrap();
+        let config = HistoryConfig::new("test").with_path(&path).with_dedup(true);
+        let editor = install(Reedline::create(), &config).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        drop(editor);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents, "a\nc\nb\n");
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Display};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+use clap::{ArgMatches, Command};
+use reedline::Reedline;
+
+use crate::panic;
+use crate::printer::Printer;
+#[cfg(feature = "tokio")]
+use crate::AsyncReplHandler;
+use crate::{CancellationToken, ControlFlow, ExecutionContext, ReplHandler, Token};
+
+/// Starts the thread of a background job
+pub(crate) type Spawner = Box<dyn Fn(JobStart) + Send + Sync>;
+
+/// Everything a background job needs to execute its command on another thread
+pub(crate) struct JobStart {
+    pub id: usize,
+    pub matches: ArgMatches,
+    pub command: Command,
+    pub printer: Printer,
+    pub globals: ArgMatches,
+    pub token: CancellationToken,
+    pub jobs: Arc<Jobs>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum JobState {
+    Running,
+    Done,
+    Failed(String),
+    Cancelled,
+    Panicked(String),
+}
+
+impl Display for JobState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobState::Running => write!(f, "Running"),
+            JobState::Done => write!(f, "Done"),
+            JobState::Failed(message) => write!(f, "Failed: {message}"),
+            JobState::Cancelled => write!(f, "Cancelled"),
+            JobState::Panicked(message) => write!(f, "Panicked: {message}"),
+        }
+    }
+}
+
+struct Job {
+    line: String,
+    token: CancellationToken,
+}
+
+#[derive(Default)]
+struct JobTable {
+    next_id: usize,
+    /// Jobs that are still running, finished ones are removed once they are announced
+    running: BTreeMap<usize, Job>,
+}
+
+/// The background jobs started with a trailing `&`
+#[derive(Default)]
+pub(crate) struct Jobs {
+    table: Mutex<JobTable>,
+    finished: Condvar,
+}
+
+/// The error of the `wait` and `kill` builtins for an id that was never assigned
+#[derive(Debug)]
+pub(crate) struct NoSuchJob(pub usize);
+
+impl Display for NoSuchJob {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "no such job: {}", self.0)
+    }
+}
+
+impl Jobs {
+    fn table(&self) -> MutexGuard<'_, JobTable> {
+        self.table.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Registers a job executing `line` and returns its id
+    pub fn start(&self, line: &str, token: CancellationToken) -> usize {
+        let mut table = self.table();
+        table.next_id += 1;
+        let id = table.next_id;
+        let line = line.to_string();
+        table.running.insert(id, Job { line, token });
+        id
+    }
+
+    /// Removes the job and returns the message announcing its completion
+    fn finish(&self, id: usize, state: JobState) -> String {
+        let job = self.table().running.remove(&id);
+        self.finished.notify_all();
+        let line = job.map(|job| job.line).unwrap_or_default();
+        format!("[{id}] {state}  {line}")
+    }
+
+    /// The status lines of all running jobs
+    pub fn list(&self) -> Vec<String> {
+        let table = self.table();
+        let running = table.running.iter();
+        running
+            .map(|(id, job)| format!("[{id}] {}  {}", JobState::Running, job.line))
+            .collect()
+    }
+
+    /// Blocks until the job `id` or, if it is `None`, all jobs have finished or `token` is
+    /// cancelled
+    pub fn wait(&self, id: Option<usize>, token: &CancellationToken) -> Result<(), NoSuchJob> {
+        let mut table = self.table();
+        if let Some(id) = id {
+            if id == 0 || id > table.next_id {
+                return Err(NoSuchJob(id));
+            }
+        }
+        loop {
+            let done = match id {
+                Some(id) => !table.running.contains_key(&id),
+                None => table.running.is_empty(),
+            };
+            if done || token.is_cancelled() {
+                return Ok(());
+            }
+            // Wake up regularly to notice a cancellation by Ctrl-C
+            table = self
+                .finished
+                .wait_timeout(table, Duration::from_millis(100))
+                .unwrap_or_else(|err| err.into_inner())
+                .0;
+        }
+    }
+
+    /// Cancels the token of the job `id`
+    pub fn kill(&self, id: usize) -> Result<(), NoSuchJob> {
+        match self.table().running.get(&id) {
+            Some(job) => {
+                job.token.cancel();
+                Ok(())
+            }
+            None => Err(NoSuchJob(id)),
+        }
+    }
+}
+
+/// Removes a trailing `&` that is not quoted or escaped from `tokens`, returning whether there
+/// was one
+pub(crate) fn strip_background(line: &str, tokens: &mut Vec<Token>) -> bool {
+    let Some(last) = tokens.last_mut() else {
+        return false;
+    };
+    let raw = line.get(last.span.clone()).unwrap_or_default();
+    if raw == "&" {
+        tokens.pop();
+        true
+    } else if raw.ends_with('&') && !raw.ends_with("\\&") && last.value.ends_with('&') {
+        last.value.pop();
+        true
+    } else {
+        false
+    }
+}
+
+/// The handler of a [`ReplContext`](crate::ReplContext) shared with the threads of its jobs
+pub(crate) enum SharedHandler<C: clap::Parser, Err> {
+    Sync(Arc<dyn ReplHandler<C, Err = Err> + Send + Sync>),
+    #[cfg(feature = "tokio")]
+    Async(Arc<dyn AsyncReplHandler<C, Err = Err> + Send + Sync>),
+}
+
+impl<C: clap::Parser, Err> Clone for SharedHandler<C, Err> {
+    fn clone(&self) -> Self {
+        match self {
+            SharedHandler::Sync(handler) => SharedHandler::Sync(handler.clone()),
+            #[cfg(feature = "tokio")]
+            SharedHandler::Async(handler) => SharedHandler::Async(handler.clone()),
+        }
+    }
+}
+
+/// Creates the [`Spawner`] that runs jobs with `handler` on a new thread
+pub(crate) fn spawner<C, Err>(handler: SharedHandler<C, Err>) -> Spawner
+where
+    C: clap::Parser + 'static,
+    Err: Debug + Display + 'static,
+{
+    Box::new(move |start: JobStart| {
+        let handler = handler.clone();
+        #[cfg(feature = "tokio")]
+        let runtime = tokio::runtime::Handle::try_current().ok();
+        std::thread::spawn(move || {
+            let state = panic::catch(|| {
+                run(
+                    &handler,
+                    &start,
+                    #[cfg(feature = "tokio")]
+                    runtime,
+                )
+            })
+            .unwrap_or_else(|report| JobState::Panicked(report.message().to_string()));
+            let message = start.jobs.finish(start.id, state);
+            start.printer.print(message);
+        });
+    })
+}
+
+fn run<C: clap::Parser, Err: Debug + Display>(
+    handler: &SharedHandler<C, Err>,
+    start: &JobStart,
+    #[cfg(feature = "tokio")] runtime: Option<tokio::runtime::Handle>,
+) -> JobState {
+    // Jobs do not have access to the editor of the prompt
+    let mut editor = Reedline::create();
+    let mut command = start.command.clone();
+    let mut context = ExecutionContext {
+        editor: &mut editor,
+        printer: start.printer.external(),
+        command: &mut command,
+        control_flow: ControlFlow::Continue,
+        interactive: true,
+        output: &start.printer,
+        globals: &start.globals,
+        cancellation: start.token.clone(),
+    };
+    let cli = match C::from_arg_matches(&start.matches) {
+        Ok(cli) => cli,
+        Err(err) => return JobState::Failed(err.to_string()),
+    };
+    let result = match handler {
+        SharedHandler::Sync(handler) => handler.on_command(&mut context, cli),
+        #[cfg(feature = "tokio")]
+        SharedHandler::Async(handler) => {
+            let future = handler.on_command(&mut context, cli);
+            let future = async {
+                tokio::select! {
+                    result = future => result,
+                    _ = start.token.cancelled() => Ok(()),
+                }
+            };
+            match runtime {
+                Some(runtime) => runtime.block_on(future),
+                None => match crate::async_handler::block_on(future) {
+                    Ok(result) => result,
+                    Err(err) => return JobState::Failed(err.to_string()),
+                },
+            }
+        }
+    };
+    match result {
+        _ if start.token.is_cancelled() => JobState::Cancelled,
+        Ok(()) => JobState::Done,
+        Err(err) => JobState::Failed(err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ShellTokenizer, Tokenizer};
+
+    /// The values left after stripping and whether `line` runs in the background
+    fn strip(line: &str) -> (Vec<String>, bool) {
+        let mut tokens = ShellTokenizer.tokenize(line).unwrap();
+        let background = strip_background(line, &mut tokens);
+        (
+            tokens.into_iter().map(|token| token.value).collect(),
+            background,
+        )
+    }
+
+    #[test]
+    fn trailing_ampersand_is_stripped() {
+        assert_eq!(strip("sleep 1 &"), (vec!["sleep".into(), "1".into()], true));
+        assert_eq!(strip("sleep 1&"), (vec!["sleep".into(), "1".into()], true));
+        assert_eq!(strip("sleep &  "), (vec!["sleep".into()], true));
+    }
+
+    #[test]
+    fn quoted_or_escaped_ampersand_is_kept() {
+        assert_eq!(strip("echo \\&"), (vec!["echo".into(), "&".into()], false));
+        assert_eq!(
+            strip("echo a\\&"),
+            (vec!["echo".into(), "a&".into()], false)
+        );
+        assert_eq!(strip("echo '&'"), (vec!["echo".into(), "&".into()], false));
+        assert_eq!(
+            strip("echo \"a&\""),
+            (vec!["echo".into(), "a&".into()], false)
+        );
+    }
+
+    #[test]
+    fn lines_without_ampersand() {
+        assert_eq!(
+            strip("echo a & b"),
+            (
+                vec!["echo".into(), "a".into(), "&".into(), "b".into()],
+                false
+            )
+        );
+        assert_eq!(strip(""), (vec![], false));
+    }
+
+    #[test]
+    fn wait_and_kill_unknown_jobs() {
+        let jobs = Jobs::default();
+        let token = CancellationToken::new();
+        assert!(jobs.wait(Some(1), &token).is_err());
+        assert!(jobs.kill(1).is_err());
+
+        let id = jobs.start("sleep", token.clone());
+        assert_eq!(jobs.list(), ["[1] Running  sleep"]);
+        jobs.kill(id).unwrap();
+        assert!(token.is_cancelled());
+        assert_eq!(jobs.finish(id, JobState::Cancelled), "[1] Cancelled  sleep");
+        assert!(jobs.list().is_empty());
+        assert!(jobs.wait(Some(id), &CancellationToken::new()).is_ok());
+        assert!(jobs.wait(None, &CancellationToken::new()).is_ok());
+    }
+}
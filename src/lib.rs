@@ -1,21 +1,62 @@
-use std::any::Any;
+use std::io::IsTerminal;
 use std::marker::PhantomData;
-use std::panic::catch_unwind;
-use std::sync::Mutex;
+use std::path::PathBuf;
+use std::sync::Arc;
 
 use std::fmt::{Debug, Display};
 
-use clap::Command;
+use clap::{Arg, ArgMatches, Command, FromArgMatches};
 
 use clap::error::RichFormatter;
 use clap::{error::ErrorKind, Error as ClapError};
-use reedline::{DefaultPrompt, DefaultPromptSegment, ExternalPrinter, Prompt, Reedline, Signal};
+use reedline::{
+    default_emacs_keybindings, ColumnarMenu, DefaultPrompt, DefaultPromptSegment, Emacs,
+    ExternalPrinter, KeyCode, KeyModifiers, Prompt, Reedline, ReedlineEvent, ReedlineMenu, Signal,
+};
 use thiserror::Error;
 
+use crate::jobs::{JobStart, Jobs, SharedHandler, Spawner};
+
 #[cfg(feature = "default_error_handler")]
 #[cfg(not(any(feature = "tracing", feature = "log")))]
 compile_error!("Feature 'tracing' or 'log' must be activated");
 
+mod analyze;
+#[cfg(feature = "tokio")]
+mod async_handler;
+mod builtins;
+mod cancel;
+mod cli;
+mod completion;
+mod error_printer;
+mod highlight;
+mod hint;
+mod history;
+mod jobs;
+#[cfg(any(feature = "log", feature = "tracing-subscriber"))]
+mod logging;
+mod panic;
+mod printer;
+mod script;
+mod tokenizer;
+#[cfg(feature = "tokio")]
+pub use async_handler::*;
+pub use builtins::Builtin;
+pub use cancel::{interrupt, CancellationToken};
+pub use completion::*;
+pub use error_printer::*;
+pub use highlight::*;
+pub use hint::*;
+pub use history::{HistoryBackend, HistoryConfig};
+#[cfg(any(feature = "log", feature = "tracing-subscriber"))]
+pub use logging::*;
+pub use panic::PanicReport;
+pub use printer::*;
+pub use script::*;
+pub use tokenizer::*;
+
+pub use nu_ansi_term;
+
 #[cfg(feature = "static_prompt")]
 mod prompt;
 #[cfg(feature = "static_prompt")]
@@ -25,12 +66,38 @@ pub struct TermReader {
     pub editor: Reedline,
     pub prompt: Box<dyn Prompt + Send>,
     pub external_printer: ExternalPrinter<String>,
+    pub highlight_styles: HighlightStyles,
+    dedup_history: bool,
+    printer: Printer,
+    completions: bool,
+    highlighting: bool,
+    hints: bool,
 }
 
 impl TermReader {
     pub fn new() -> TermReader {
         let external_printer = ExternalPrinter::default();
-        let editor = Reedline::create().with_external_printer(external_printer.clone());
+        let mut keybindings = default_emacs_keybindings();
+        keybindings.add_binding(
+            KeyModifiers::NONE,
+            KeyCode::Tab,
+            ReedlineEvent::UntilFound(vec![
+                ReedlineEvent::Menu(COMPLETION_MENU.to_string()),
+                ReedlineEvent::MenuNext,
+            ]),
+        );
+        keybindings.add_binding(
+            KeyModifiers::SHIFT,
+            KeyCode::BackTab,
+            ReedlineEvent::MenuPrevious,
+        );
+        let completion_menu = ColumnarMenu::default().with_name(COMPLETION_MENU);
+        let editor = Reedline::create()
+            .with_external_printer(external_printer.clone())
+            .with_edit_mode(Box::new(Emacs::new(keybindings)))
+            .with_menu(ReedlineMenu::EngineCompleter(Box::new(completion_menu)))
+            .with_quick_completions(true)
+            .with_partial_completions(true);
         let prompt = DefaultPrompt::new(
             DefaultPromptSegment::Basic("> ".into()),
             DefaultPromptSegment::Empty,
@@ -38,19 +105,86 @@ impl TermReader {
         Self {
             editor,
             prompt: Box::new(prompt),
+            printer: Printer::new(external_printer.clone()),
             external_printer,
+            highlight_styles: HighlightStyles::default(),
+            dedup_history: false,
+            completions: true,
+            highlighting: true,
+            hints: true,
         }
     }
 
+    /// Replaces the in-memory history with a persistent one
+    pub fn with_history(mut self, config: HistoryConfig) -> std::io::Result<Self> {
+        self.editor = history::install(self.editor, &config)?;
+        self.dedup_history = config.dedup;
+        Ok(self)
+    }
+
+    /// Whether the [`ReplContext`] installs its [`ReplCompleter`] on the editor, disable this to
+    /// keep a completer of your own
+    pub fn with_completions(mut self, enabled: bool) -> Self {
+        self.completions = enabled;
+        self
+    }
+
+    /// Whether the [`ReplContext`] installs its [`ReplHighlighter`] on the editor, disable this
+    /// to keep a highlighter of your own
+    pub fn with_highlighting(mut self, enabled: bool) -> Self {
+        self.highlighting = enabled;
+        self
+    }
+
+    /// Whether the [`ReplContext`] installs its [`ReplHinter`] on the editor, disable this to
+    /// keep a hinter of your own
+    pub fn with_hints(mut self, enabled: bool) -> Self {
+        self.hints = enabled;
+        self
+    }
+
     pub fn set_prompt<P: Prompt + Send + 'static>(&mut self, prompt: P) {
         self.prompt = Box::new(prompt);
     }
+
+    /// A handle for printing from other threads that stays valid as long as the reader lives
+    pub fn printer(&self) -> Printer {
+        self.printer.clone()
+    }
+}
+
+impl Default for TermReader {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 pub struct ReplContext<C: clap::Parser, Err: Debug + Display> {
-    handler: Box<dyn ReplHandler<C, Err = Err> + Send>,
+    handler: Handler<C, Err>,
     pub command: Command,
     pub reader: TermReader,
+    tokenizer: Arc<dyn Tokenizer + Send + Sync>,
+    value_completers: ValueCompleters,
+    builtins: Vec<Builtin>,
+    script_error_policy: ScriptErrorPolicy,
+    /// The canonical paths of the files that are currently being executed
+    source_stack: Vec<PathBuf>,
+    max_source_depth: usize,
+    /// Whether commands are read from a terminal through the editor
+    interactive: bool,
+    global_args: Vec<Arg>,
+    global_matches: ArgMatches,
+    /// The token of the command that is executed
+    cancellation: CancellationToken,
+    jobs: Arc<Jobs>,
+    /// Starts background jobs, `None` if the handler cannot be shared with other threads
+    spawn_job: Option<Spawner>,
+    /// Whether a Ctrl-C handler is installed to cancel running commands
+    cancel_on_interrupt: bool,
+    /// The line read by the editor that is executed, for the [`ErrorContext`]
+    last_line: Option<String>,
+    #[cfg(any(feature = "log", feature = "tracing-subscriber"))]
+    log_filter: Option<LogFilter>,
     _data: PhantomData<C>,
 }
 
@@ -59,15 +193,158 @@ impl<C: clap::Parser + Debug, Err: Debug + Display> ReplContext<C, Err> {
         reader: TermReader,
         handler: impl ReplHandler<C, Err = Err> + Send + 'static,
     ) -> Self {
-        let mut command = C::command().multicall(true);
-        command.build();
+        Self::with_handler(reader, Handler::Sync(Box::new(handler)), None)
+    }
 
-        Self {
-            handler: Box::new(handler),
-            command,
+    /// Like [`ReplContext::new`], but shares the handler with the threads of background jobs,
+    /// which [`Builtin::Jobs`] requires
+    pub fn new_shared(
+        reader: TermReader,
+        handler: impl ReplHandler<C, Err = Err> + Send + Sync + 'static,
+    ) -> Self
+    where
+        C: 'static,
+        Err: 'static,
+    {
+        let handler = Arc::new(handler);
+        let spawn_job = jobs::spawner(SharedHandler::Sync(handler.clone()));
+        Self::with_handler(reader, Handler::Sync(Box::new(handler)), Some(spawn_job))
+    }
+
+    fn with_handler(
+        reader: TermReader,
+        handler: Handler<C, Err>,
+        spawn_job: Option<Spawner>,
+    ) -> Self {
+        let mut context = Self {
+            spawn_job,
+            jobs: Arc::default(),
+            handler,
+            command: Self::build_command(&[]),
             reader,
+            tokenizer: Arc::new(ShellTokenizer),
+            value_completers: ValueCompleters::default(),
+            builtins: Vec::new(),
+            script_error_policy: ScriptErrorPolicy::default(),
+            source_stack: Vec::new(),
+            max_source_depth: DEFAULT_MAX_SOURCE_DEPTH,
+            interactive: true,
+            global_args: Vec::new(),
+            global_matches: ArgMatches::default(),
+            cancellation: CancellationToken::new(),
+            cancel_on_interrupt: false,
+            last_line: None,
+            #[cfg(any(feature = "log", feature = "tracing-subscriber"))]
+            log_filter: None,
             _data: PhantomData,
+        };
+        context.update_editor();
+        context
+    }
+
+    fn build_command(builtins: &[Builtin]) -> Command {
+        let mut command = C::command().multicall(true);
+        for builtin in builtins {
+            if *builtin == Builtin::Help {
+                command = command.disable_help_subcommand(true);
+            }
+            command = command.subcommand(builtin.command());
+        }
+        command.build();
+        command
+    }
+
+    /// Merges the given [`Builtin`] commands into [`ReplContext::command`].
+    ///
+    /// Builtins whose name or alias is already used by a command of `C` are skipped, as is
+    /// [`Builtin::Jobs`] if the handler is not shared with other threads. The command is
+    /// rebuilt from `C`, so changes made to [`ReplContext::command`] before are lost.
+    pub fn enable_builtins(&mut self, builtins: impl IntoIterator<Item = Builtin>) {
+        let own = C::command();
+        for builtin in builtins {
+            let builtin_command = builtin.command();
+            let taken = std::iter::once(builtin_command.get_name())
+                .chain(builtin_command.get_all_aliases())
+                .any(|name| own.find_subcommand(name).is_some());
+            let shared = builtin != Builtin::Jobs || self.spawn_job.is_some();
+            if !self.builtins.contains(&builtin) && !taken && shared {
+                self.builtins.push(builtin);
+            }
+        }
+        self.command = Self::build_command(&self.builtins);
+        self.update_editor();
+    }
+
+    /// Installs a process-wide Ctrl-C handler when the first command runs, which cancels the
+    /// [`CancellationToken`] of running commands and terminates the process on a second Ctrl-C,
+    /// see [`interrupt`]. Without it, Ctrl-C while a command runs terminates the process.
+    ///
+    /// The handler cannot be removed and prevents the application from installing its own
+    /// one with `ctrlc`. Applications with a handler of their own can call [`interrupt`] from
+    /// it instead.
+    pub fn set_cancel_on_interrupt(&mut self, enabled: bool) {
+        self.cancel_on_interrupt = enabled;
+    }
+
+    /// Sets the filter changed by [`Builtin::LogLevel`], usually [`ReplLogger::filter`]
+    #[cfg(any(feature = "log", feature = "tracing-subscriber"))]
+    pub fn set_log_filter(&mut self, filter: LogFilter) {
+        self.log_filter = Some(filter);
+    }
+
+    /// Replaces the [`ShellTokenizer`] used to split command lines into arguments
+    pub fn set_tokenizer<T: Tokenizer + Send + Sync + 'static>(&mut self, tokenizer: T) {
+        self.tokenizer = Arc::new(tokenizer);
+        self.update_editor();
+    }
+
+    /// Registers a [`ValueCompleter`] for the values of all arguments with the id `arg`
+    pub fn register_value_completer(
+        &mut self,
+        arg: impl Into<String>,
+        completer: impl ValueCompleter + 'static,
+    ) {
+        self.value_completers.register(arg, completer);
+        self.update_editor();
+    }
+
+    /// Sets the [`ValueCompleter`] used for arguments without a registered completer
+    pub fn set_value_completer(&mut self, completer: impl ValueCompleter + 'static) {
+        self.value_completers.set_fallback(completer);
+        self.update_editor();
+    }
+
+    /// Reinstalls the completer, highlighter and hinter of the editor so that they pick up changes
+    /// made to [`ReplContext::command`] or [`TermReader::highlight_styles`]. Those disabled with
+    /// [`TermReader::with_completions`], [`TermReader::with_highlighting`] or
+    /// [`TermReader::with_hints`] are left alone.
+    pub fn update_editor(&mut self) {
+        let mut editor = std::mem::replace(&mut self.reader.editor, Reedline::create());
+        if self.reader.completions {
+            let completer = ReplCompleter::new(self.command.clone(), self.tokenizer.clone())
+                .with_value_completers(self.value_completers.clone());
+            editor = editor.with_completer(Box::new(completer));
+        }
+        if self.reader.highlighting {
+            let highlighter = ReplHighlighter::new(self.command.clone(), self.tokenizer.clone())
+                .with_styles(self.reader.highlight_styles);
+            editor = editor.with_highlighter(Box::new(highlighter));
         }
+        if self.reader.hints {
+            let hinter = ReplHinter::new(self.command.clone(), self.tokenizer.clone())
+                .with_style(self.reader.highlight_styles.hint);
+            editor = editor.with_hinter(Box::new(hinter));
+        }
+        self.reader.editor = editor;
+    }
+
+    pub fn tokenizer(&self) -> &(dyn Tokenizer + Send + Sync) {
+        &*self.tokenizer
+    }
+
+    /// See [`TermReader::printer`]
+    pub fn printer(&self) -> Printer {
+        self.reader.printer()
     }
 }
 
@@ -75,12 +352,44 @@ pub struct ExecutionContext<'a> {
     pub editor: &'a mut Reedline,
     pub printer: &'a ExternalPrinter<String>,
     pub command: &'a mut Command,
+    control_flow: ControlFlow,
+    interactive: bool,
+    output: &'a Printer,
+    globals: &'a ArgMatches,
+    cancellation: CancellationToken,
 }
 
 impl<'a> ExecutionContext<'a> {
+    /// Prints a line to the terminal immediately, or through the external printer above the
+    /// prompt if a background job prints while it is shown
     #[inline]
     pub fn print(&self, display: impl Display) {
-        self.printer.print(format!("{}", display)).unwrap();
+        self.output.print(display.to_string());
+    }
+
+    /// A [`Printer`] that can be moved to other threads
+    pub fn printer_handle(&self) -> Printer {
+        self.output.clone()
+    }
+
+    /// Whether commands are read from a terminal. Output should not contain colors otherwise.
+    pub fn is_interactive(&self) -> bool {
+        self.interactive
+    }
+
+    /// The token that is cancelled when Ctrl-C is pressed while the command runs, see
+    /// [`ReplContext::set_cancel_on_interrupt`]
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.cancellation
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+
+    /// The global options passed to the process, see [`ReplContext::set_global_args`]
+    pub fn globals<G: FromArgMatches>(&self) -> Result<G, ClapError> {
+        G::from_arg_matches(self.globals)
     }
 
     #[inline]
@@ -91,6 +400,70 @@ impl<'a> ExecutionContext<'a> {
     pub fn error(&mut self, kind: ErrorKind, message: impl Display) -> ClapError {
         self.command.error(kind, message)
     }
+
+    /// Ends the read loop with `code` once the current command completed successfully
+    pub fn request_exit(&mut self, code: i32) {
+        self.control_flow = ControlFlow::Exit(ExitStatus(code));
+    }
+
+    /// Rebuilds the command and restarts the read loop once the current command completed
+    /// successfully
+    pub fn request_restart(&mut self) {
+        self.control_flow = ControlFlow::Restart;
+    }
+
+    pub fn set_control_flow(&mut self, control_flow: ControlFlow) {
+        self.control_flow = control_flow;
+    }
+
+    pub fn control_flow(&self) -> ControlFlow {
+        self.control_flow
+    }
+}
+
+/// The exit code requested by the `exit` [`Builtin`] or [`ExecutionContext::request_exit`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExitStatus(pub i32);
+
+impl ExitStatus {
+    pub const SUCCESS: ExitStatus = ExitStatus(0);
+
+    pub fn code(self) -> i32 {
+        self.0
+    }
+
+    pub fn success(self) -> bool {
+        self.0 == 0
+    }
+}
+
+/// Codes outside of `0..=255`, which the process cannot report, become
+/// [`ExitCode::FAILURE`](std::process::ExitCode::FAILURE)
+impl From<ExitStatus> for std::process::ExitCode {
+    fn from(status: ExitStatus) -> Self {
+        match u8::try_from(status.0) {
+            Ok(code) => std::process::ExitCode::from(code),
+            Err(_) => std::process::ExitCode::FAILURE,
+        }
+    }
+}
+
+/// What the read loop does after a command
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ControlFlow {
+    #[default]
+    Continue,
+    Exit(ExitStatus),
+    /// Rebuilds [`ReplContext::command`] from the clap parser and the enabled [`Builtin`]s,
+    /// reinstalls the editor integrations and continues reading
+    Restart,
+}
+
+/// The handler a [`ReplContext`] passes commands to
+enum Handler<C: clap::Parser, Err> {
+    Sync(Box<dyn ReplHandler<C, Err = Err> + Send>),
+    #[cfg(feature = "tokio")]
+    Async(Arc<dyn AsyncReplHandler<C, Err = Err> + Send + Sync>),
 }
 
 pub trait ReplHandler<C: clap::Parser> {
@@ -98,6 +471,13 @@ pub trait ReplHandler<C: clap::Parser> {
     fn on_command(&self, ctx: &mut ExecutionContext, command: C) -> Result<(), Self::Err>;
 }
 
+impl<C: clap::Parser, H: ReplHandler<C> + ?Sized> ReplHandler<C> for Arc<H> {
+    type Err = H::Err;
+    fn on_command(&self, ctx: &mut ExecutionContext, command: C) -> Result<(), Self::Err> {
+        (**self).on_command(ctx, command)
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum ReplError<Err: Debug + Display> {
     #[error("Read was interrupted")]
@@ -108,22 +488,107 @@ pub enum ReplError<Err: Debug + Display> {
     Clap(#[from] ClapError),
     #[error("{0}")]
     Parse(clap::error::Error<RichFormatter>),
-    #[error("Command execution panicked")]
-    Panic(Box<dyn Any + Send>),
+    #[error(transparent)]
+    Tokenize(#[from] TokenizeError),
+    #[error("{0}: event not found")]
+    EventNotFound(String),
+    #[error("{location}: {}", **.error)]
+    Script {
+        location: ScriptLocation,
+        error: Box<ReplError<Err>>,
+    },
+    #[error("{}: {error}", .path.display())]
+    ScriptFile {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    #[error("{}: file is already being sourced", .0.display())]
+    SourceCycle(PathBuf),
+    #[error("Maximum source depth of {0} exceeded")]
+    SourceDepth(usize),
+    #[error("Command was cancelled")]
+    Cancelled,
+    #[error("Command execution {0}")]
+    Panic(PanicReport),
     #[error(transparent)]
     Io(#[from] std::io::Error),
     #[error("An error occurred while executing a command {0}")]
     ExecutionError(Err),
 }
 
-pub trait ErrorHandler<Err: Debug + Display>:
-    Fn(ReplError<Err>) -> Result<(), ReplError<Err>>
-{
+/// Decides what happens with an error of the read loop, returning it ends the loop.
+///
+/// Implemented by closures taking only the error, like `default_error_handler`, and by
+/// closures that also take the [`ErrorContext`] wrapped with [`with_context`].
+pub trait ErrorHandler<Err: Debug + Display> {
+    fn handle(
+        &self,
+        ctx: &mut ErrorContext<'_>,
+        error: ReplError<Err>,
+    ) -> Result<(), ReplError<Err>>;
 }
 
 impl<Err: ReplExecutionError, F: Fn(ReplError<Err>) -> Result<(), ReplError<Err>>> ErrorHandler<Err>
     for F
 {
+    fn handle(
+        &self,
+        _ctx: &mut ErrorContext<'_>,
+        error: ReplError<Err>,
+    ) -> Result<(), ReplError<Err>> {
+        self(error)
+    }
+}
+
+/// An [`ErrorHandler`] created by [`with_context`]
+#[derive(Debug, Clone, Copy)]
+pub struct WithContext<F>(pub F);
+
+impl<Err, F> ErrorHandler<Err> for WithContext<F>
+where
+    Err: ReplExecutionError,
+    F: Fn(&mut ErrorContext<'_>, ReplError<Err>) -> Result<(), ReplError<Err>>,
+{
+    fn handle(
+        &self,
+        ctx: &mut ErrorContext<'_>,
+        error: ReplError<Err>,
+    ) -> Result<(), ReplError<Err>> {
+        (self.0)(ctx, error)
+    }
+}
+
+/// Turns a closure that takes the [`ErrorContext`] of an error into an [`ErrorHandler`]
+pub fn with_context<Err, F>(handler: F) -> WithContext<F>
+where
+    Err: ReplExecutionError,
+    F: Fn(&mut ErrorContext<'_>, ReplError<Err>) -> Result<(), ReplError<Err>>,
+{
+    WithContext(handler)
+}
+
+/// The state of the REPL an [`ErrorHandler`] can access
+pub struct ErrorContext<'a> {
+    pub editor: &'a mut Reedline,
+    pub command: &'a mut Command,
+    line: Option<&'a str>,
+    printer: &'a Printer,
+}
+
+impl<'a> ErrorContext<'a> {
+    /// The line that failed, after history expansion. `None` if the error did not occur while
+    /// executing a line, e.g. for [`ReplError::EOF`] or errors of the process arguments.
+    pub fn line(&self) -> Option<&str> {
+        self.line
+    }
+
+    pub fn printer(&self) -> &Printer {
+        self.printer
+    }
+
+    pub fn print(&self, display: impl Display) {
+        self.printer.print(display);
+    }
 }
 
 pub trait ReplExecutionError: Debug + Display {}
@@ -164,7 +629,42 @@ pub fn default_error_handler<Err: ReplExecutionError>(
             log::warn!("{}", err);
             Ok(())
         }
-        ReplError::Panic(_) => Err(error),
+        ReplError::Tokenize(_)
+        | ReplError::EventNotFound(_)
+        | ReplError::ScriptFile { .. }
+        | ReplError::SourceCycle(_)
+        | ReplError::SourceDepth(_)
+        | ReplError::Cancelled => {
+            #[cfg(feature = "tracing")]
+            tracing::warn!("{}", error);
+            #[cfg(feature = "log")]
+            log::warn!("{}", error);
+            Ok(())
+        }
+        ReplError::Script { error: inner, .. } => match **inner {
+            ReplError::Interrupt | ReplError::EOF | ReplError::Io(_) => Err(error),
+            ReplError::Panic(_) => {
+                #[cfg(feature = "tracing")]
+                tracing::error!("{}", error);
+                #[cfg(feature = "log")]
+                log::error!("{}", error);
+                Err(error)
+            }
+            _ => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!("{}", error);
+                #[cfg(feature = "log")]
+                log::warn!("{}", error);
+                Ok(())
+            }
+        },
+        ReplError::Panic(_) => {
+            #[cfg(feature = "tracing")]
+            tracing::error!("{}", error);
+            #[cfg(feature = "log")]
+            log::error!("{}", error);
+            Err(error)
+        }
         ReplError::Io(_) => Err(error),
         ReplError::ExecutionError(err) => {
             #[cfg(feature = "tracing")]
@@ -177,41 +677,66 @@ pub fn default_error_handler<Err: ReplExecutionError>(
 }
 
 impl<C: clap::Parser + Debug, Err: Debug + Display> ReplContext<C, Err> {
+    /// Reads and executes commands until an exit is requested or `handle_error` returns an error.
+    ///
+    /// Returns the requested [`ExitStatus`] if the loop was ended by the `exit` [`Builtin`], by
+    /// [`ExecutionContext::request_exit`] or if `handle_error` passes on [`ReplError::EOF`], e.g.
+    /// after Ctrl-D was pressed.
+    ///
+    /// If stdin is not a terminal, lines are read from it without the editor, prompt or colors
+    /// and output is written to stdout directly. Errors are passed to `handle_error` with the
+    /// line they occurred in and the loop ends at the end of the input, with a failure status
+    /// if any line failed.
+    ///
+    /// Ctrl-C while a command runs terminates the process unless
+    /// [`ReplContext::set_cancel_on_interrupt`] is enabled, which cancels the command instead.
     pub fn read_loop<F: ErrorHandler<Err>>(
         mut self,
         handle_error: F,
-    ) -> Result<(), ReplError<Err>> {
+    ) -> Result<ExitStatus, ReplError<Err>> {
+        if !std::io::stdin().is_terminal() {
+            return self.read_plain_loop(handle_error);
+        }
+        let _loop = cancel::enter_loop();
         let command = &mut self.command.clone();
         loop {
             match self.read_with_command(command) {
-                Ok(_) => {}
+                Ok(ControlFlow::Continue) => {}
+                Ok(ControlFlow::Exit(status)) => return Ok(status),
+                Ok(ControlFlow::Restart) => self.restart(command),
                 Err(err) => {
-                    if let Err(err) = handle_error(err) {
-                        return Err(err);
+                    let line = self.last_line.take();
+                    let mut context = self.error_context(command, line.as_deref());
+                    match handle_error.handle(&mut context, err) {
+                        Ok(()) => {}
+                        Err(ReplError::EOF) => return Ok(ExitStatus::SUCCESS),
+                        Err(err) => return Err(err),
                     }
                 }
             }
         }
     }
-    pub fn read(&mut self) -> Result<(), ReplError<Err>> {
+    pub fn read(&mut self) -> Result<ControlFlow, ReplError<Err>> {
         self.read_with_command(&mut self.command.clone())
     }
 
-    pub fn read_with_command(&mut self, command: &mut Command) -> Result<(), ReplError<Err>> {
+    pub fn read_with_command(
+        &mut self,
+        command: &mut Command,
+    ) -> Result<ControlFlow, ReplError<Err>> {
+        self.last_line = None;
+        let prompt_guard = self.reader.printer.activate_prompt();
         let sig = self.reader.editor.read_line(&*self.reader.prompt);
+        drop(prompt_guard);
         match sig {
             Ok(Signal::Success(buffer)) => {
-                let mtx = Mutex::new(self);
-                let cmd_mtx = Mutex::new(command);
-                let catch_res = catch_unwind(|| {
-                    let mut command = cmd_mtx.lock().unwrap();
-                    mtx.lock().unwrap().execute_command(&mut *command, &buffer)
-                    // self.execute_command(&mut command, &buffer);
-                });
-                match catch_res {
-                    Ok(res) => res,
-                    Err(err) => Err(ReplError::Panic(err)),
-                }
+                let buffer = self.accept_line(buffer)?;
+                #[cfg(feature = "sqlite")]
+                let started = history::CommandStart::now();
+                let res = self.execute_line(command, &buffer);
+                #[cfg(feature = "sqlite")]
+                started.record(&mut self.reader.editor, res.is_ok());
+                res
             }
             Ok(Signal::CtrlC) => Err(ReplError::Interrupt),
             Ok(Signal::CtrlD) => Err(ReplError::EOF),
@@ -219,26 +744,218 @@ impl<C: clap::Parser + Debug, Err: Debug + Display> ReplContext<C, Err> {
         }
     }
 
-    fn execute_command(&mut self, command: &mut Command, line: &str) -> Result<(), ReplError<Err>> {
-        if line.is_empty() {
-            return Ok(());
+    /// Updates the history with a line read by the editor and expands history designators
+    fn accept_line(&mut self, mut buffer: String) -> Result<String, ReplError<Err>> {
+        self.last_line = Some(buffer.clone());
+        if self.reader.dedup_history {
+            history::remove_duplicates(&mut self.reader.editor, &buffer);
         }
+        if self.builtins.contains(&Builtin::History) {
+            if let Some(expanded) = builtins::expand_history(&buffer, self.reader.editor.history())?
+            {
+                self.reader.printer.print(expanded.clone());
+                self.last_line = Some(expanded.clone());
+                buffer = expanded;
+            }
+        }
+        Ok(buffer)
+    }
 
-        match command.try_get_matches_from_mut(line.split_whitespace()) {
-            Ok(cli_raw) => match C::from_arg_matches(&cli_raw) {
-                Ok(cli) => {
-                    let mut context = ExecutionContext {
-                        editor: &mut self.reader.editor,
-                        printer: &self.reader.external_printer,
-                        command,
-                    };
-                    self.handler
-                        .on_command(&mut context, cli)
-                        .map_err(|err| ReplError::ExecutionError(err))
-                }
-                Err(err) => Err(ReplError::Parse(err)),
-            },
-            Err(err) => Err(ReplError::Parse(err)),
+    /// Executes `line`, turning a panic of the handler into [`ReplError::Panic`]
+    fn execute_line(
+        &mut self,
+        command: &mut Command,
+        line: &str,
+    ) -> Result<ControlFlow, ReplError<Err>> {
+        self.execute_guarded(command, |context, command| {
+            context.execute_command(command, line)
+        })
+    }
+
+    /// Runs `execute` with a new [`CancellationToken`] that is cancelled by Ctrl-C, turning
+    /// a cancellation into [`ReplError::Cancelled`] and a panic into [`ReplError::Panic`]
+    fn execute_guarded(
+        &mut self,
+        command: &mut Command,
+        execute: impl FnOnce(&mut Self, &mut Command) -> Result<ControlFlow, ReplError<Err>>,
+    ) -> Result<ControlFlow, ReplError<Err>> {
+        let token = CancellationToken::new();
+        self.cancellation = token.clone();
+        let _guard = cancel::watch(&token, self.cancel_on_interrupt);
+        let result = match panic::catch(|| execute(self, command)) {
+            Ok(res) => res,
+            Err(report) => Err(ReplError::Panic(report)),
+        };
+        if token.is_cancelled() {
+            Err(ReplError::Cancelled)
+        } else {
+            result
+        }
+    }
+
+    fn restart(&mut self, command: &mut Command) {
+        self.command = Self::build_command(&self.builtins);
+        self.update_editor();
+        *command = self.command.clone();
+    }
+
+    fn execute_command(
+        &mut self,
+        command: &mut Command,
+        line: &str,
+    ) -> Result<ControlFlow, ReplError<Err>> {
+        match self.parse_line(command, line)? {
+            Some((cli_raw, true)) if !self.is_builtin(&cli_raw) => {
+                self.spawn_job(command, cli_raw, line)
+            }
+            Some((cli_raw, _)) => self.execute_matches(command, &cli_raw),
+            None => Ok(ControlFlow::Continue),
+        }
+    }
+
+    fn is_builtin(&self, cli_raw: &ArgMatches) -> bool {
+        cli_raw
+            .subcommand_name()
+            .is_some_and(|name| self.builtins.iter().any(|b| b.name() == name))
+    }
+
+    /// The file to execute if `cli_raw` is the [`Builtin::Source`] command
+    #[cfg(feature = "tokio")]
+    fn source_path<'m>(&self, cli_raw: &'m ArgMatches) -> Option<&'m PathBuf> {
+        match cli_raw.subcommand() {
+            Some((name, matches))
+                if name == Builtin::Source.name() && self.builtins.contains(&Builtin::Source) =>
+            {
+                matches.get_one::<PathBuf>("file")
+            }
+            _ => None,
         }
     }
+
+    /// Executes `cli_raw` on a new thread and announces its job id
+    fn spawn_job(
+        &mut self,
+        command: &Command,
+        cli_raw: ArgMatches,
+        line: &str,
+    ) -> Result<ControlFlow, ReplError<Err>> {
+        let token = CancellationToken::new();
+        let line = line.trim_end().trim_end_matches('&').trim_end();
+        let id = self.jobs.start(line, token.clone());
+        let Some(spawn_job) = &self.spawn_job else {
+            unreachable!("Builtin::Jobs is only enabled with a shared handler")
+        };
+        spawn_job(JobStart {
+            id,
+            matches: cli_raw,
+            command: command.clone(),
+            printer: self.reader.printer.clone(),
+            globals: self.global_matches.clone(),
+            token,
+            jobs: self.jobs.clone(),
+        });
+        self.reader.printer.print(format!("[{id}] {line}"));
+        Ok(ControlFlow::Continue)
+    }
+
+    /// Tokenizes and parses `line`, returning `None` if it is empty.
+    ///
+    /// An unquoted `~` at the start of a value of a path-hinted argument is replaced with the
+    /// home directory. If [`Builtin::Jobs`] is enabled, a trailing `&` is removed and reported as `true`.
+    fn parse_line(
+        &self,
+        command: &mut Command,
+        line: &str,
+    ) -> Result<Option<(ArgMatches, bool)>, ReplError<Err>> {
+        let mut tokens = self.tokenizer.tokenize(line)?;
+        let background =
+            self.builtins.contains(&Builtin::Jobs) && jobs::strip_background(line, &mut tokens);
+        if tokens.is_empty() {
+            return Ok(None);
+        }
+        completion::expand_home(command, line, &mut tokens);
+        command
+            .try_get_matches_from_mut(tokens.into_iter().map(|token| token.value))
+            .map(|matches| Some((matches, background)))
+            .map_err(ReplError::Parse)
+    }
+
+    fn execute_matches(
+        &mut self,
+        command: &mut Command,
+        cli_raw: &ArgMatches,
+    ) -> Result<ControlFlow, ReplError<Err>> {
+        if let Some(result) = self.execute_builtin(command, cli_raw) {
+            return result;
+        }
+        let cli = C::from_arg_matches(cli_raw).map_err(ReplError::Parse)?;
+        let (handler, mut context) = self.execution_context(command);
+        let result = match handler {
+            Handler::Sync(handler) => handler.on_command(&mut context, cli),
+            #[cfg(feature = "tokio")]
+            Handler::Async(handler) => {
+                async_handler::block_on(handler.on_command(&mut context, cli))?
+            }
+        };
+        result
+            .map(|_| context.control_flow)
+            .map_err(ReplError::ExecutionError)
+    }
+
+    /// Executes `cli_raw` if it is a [`Builtin`]
+    fn execute_builtin(
+        &mut self,
+        command: &mut Command,
+        cli_raw: &ArgMatches,
+    ) -> Option<Result<ControlFlow, ReplError<Err>>> {
+        let (name, matches) = cli_raw.subcommand()?;
+        let builtin = *self.builtins.iter().find(|b| b.name() == name)?;
+        if builtin == Builtin::Source {
+            let path = matches.get_one::<PathBuf>("file").unwrap();
+            return Some(self.source(command, path));
+        }
+        #[cfg(any(feature = "log", feature = "tracing-subscriber"))]
+        if builtin == Builtin::LogLevel {
+            let filter = self.log_filter.clone();
+            let (_, mut context) = self.execution_context(command);
+            return Some(builtins::run_loglevel(
+                &mut context,
+                matches,
+                filter.as_ref(),
+            ));
+        }
+        let jobs = self.jobs.clone();
+        let (_, mut context) = self.execution_context(command);
+        Some(builtin.run(&mut context, matches, &jobs))
+    }
+
+    pub(crate) fn error_context<'a>(
+        &'a mut self,
+        command: &'a mut Command,
+        line: Option<&'a str>,
+    ) -> ErrorContext<'a> {
+        ErrorContext {
+            editor: &mut self.reader.editor,
+            command,
+            line,
+            printer: &self.reader.printer,
+        }
+    }
+
+    fn execution_context<'a>(
+        &'a mut self,
+        command: &'a mut Command,
+    ) -> (&'a Handler<C, Err>, ExecutionContext<'a>) {
+        let context = ExecutionContext {
+            editor: &mut self.reader.editor,
+            printer: &self.reader.external_printer,
+            command,
+            control_flow: ControlFlow::Continue,
+            interactive: self.interactive,
+            output: &self.reader.printer,
+            globals: &self.global_matches,
+            cancellation: self.cancellation.clone(),
+        };
+        (&self.handler, context)
+    }
 }
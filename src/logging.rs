@@ -0,0 +1,488 @@
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use thiserror::Error;
+
+use crate::{Printer, Severity};
+
+/// A [`log::Log`] implementation and [`tracing_subscriber::Layer`] that prints records
+/// through a [`Printer`], so that they appear above the prompt instead of garbling it
+#[derive(Debug, Clone)]
+pub struct ReplLogger {
+    printer: Printer,
+    filter: LogFilter,
+    with_target: bool,
+}
+
+impl ReplLogger {
+    pub fn new(printer: Printer) -> Self {
+        Self {
+            printer,
+            filter: LogFilter::default(),
+            with_target: false,
+        }
+    }
+
+    /// Skips records that are less severe than `level`, defaults to [`Severity::Info`]
+    pub fn with_level(mut self, level: Severity) -> Self {
+        self.filter = LogFilter::new(level);
+        self
+    }
+
+    /// Replaces the filter deciding which records are printed
+    pub fn with_filter(mut self, filter: LogFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Prefixes messages with the target of the record, e.g. the module path
+    pub fn with_target(mut self, with_target: bool) -> Self {
+        self.with_target = with_target;
+        self
+    }
+
+    /// A handle to the filter of the logger, which can be changed while it is installed
+    pub fn filter(&self) -> LogFilter {
+        self.filter.clone()
+    }
+
+    /// Installs the logger as the global [`log`] logger
+    #[cfg(feature = "log")]
+    pub fn init(self) -> Result<(), log::SetLoggerError> {
+        let filter = self.filter.clone();
+        log::set_logger(Box::leak(Box::new(self)))?;
+        filter.write().log_installed = true;
+        filter.update_max_level();
+        Ok(())
+    }
+
+    fn print(&self, severity: Severity, target: &str, message: impl Display) {
+        if self.with_target {
+            self.printer.log(severity, format!("{target}: {message}"))
+        } else {
+            self.printer.log(severity, message)
+        }
+    }
+}
+
+/// The error of a log directive that is neither `LEVEL` nor `TARGET=LEVEL`
+#[derive(Debug, Error)]
+#[error("invalid log directive '{0}', expected LEVEL or TARGET=LEVEL with one of error, warn, info, debug, trace or off")]
+pub struct ParseDirectiveError(String);
+
+/// Sets the level of all targets or, if `target` is set, of the target and its submodules.
+/// A level of `None` turns logging off.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Directive {
+    target: Option<String>,
+    level: Option<Severity>,
+}
+
+impl FromStr for Directive {
+    type Err = ParseDirectiveError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (target, level) = match s.split_once('=') {
+            Some((target, level)) => (Some(target.trim()), level.trim()),
+            None => (None, s),
+        };
+        let level = match level.to_ascii_lowercase().as_str() {
+            "off" => None,
+            "error" => Some(Severity::Error),
+            "warn" => Some(Severity::Warn),
+            "info" => Some(Severity::Info),
+            "debug" => Some(Severity::Debug),
+            "trace" => Some(Severity::Trace),
+            _ => return Err(ParseDirectiveError(s.to_string())),
+        };
+        if target.is_some_and(str::is_empty) {
+            return Err(ParseDirectiveError(s.to_string()));
+        }
+        Ok(Self {
+            target: target.map(str::to_string),
+            level,
+        })
+    }
+}
+
+impl Display for Directive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(target) = &self.target {
+            write!(f, "{target}=")?;
+        }
+        match self.level {
+            Some(level) => write!(f, "{level}"),
+            None => write!(f, "off"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Directives {
+    default: Option<Severity>,
+    /// Directives with a target, at most one per target
+    targets: Vec<Directive>,
+}
+
+impl Directives {
+    fn apply(&mut self, directive: Directive) {
+        if directive.target.is_none() {
+            self.default = directive.level;
+        } else if let Some(existing) = self
+            .targets
+            .iter_mut()
+            .find(|d| d.target == directive.target)
+        {
+            existing.level = directive.level;
+        } else {
+            self.targets.push(directive);
+        }
+    }
+
+    /// The level of the most specific directive matching `target`
+    fn level(&self, target: &str) -> Option<Severity> {
+        self.targets
+            .iter()
+            .filter_map(|directive| Some((directive.target.as_deref()?, directive.level)))
+            .filter(|(prefix, _)| {
+                target
+                    .strip_prefix(prefix)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.default, |(_, level)| level)
+    }
+
+    /// The most verbose level of any directive
+    #[cfg(feature = "log")]
+    fn max_level(&self) -> Option<Severity> {
+        let levels = self.targets.iter().map(|directive| directive.level);
+        levels.chain([self.default]).max().flatten()
+    }
+}
+
+#[derive(Debug)]
+struct FilterState {
+    current: Directives,
+    initial: Directives,
+    /// Whether the filter belongs to the global [`log`] logger, whose maximum level has to
+    /// follow changes
+    #[cfg(feature = "log")]
+    log_installed: bool,
+}
+
+/// A shared handle to the levels a [`ReplLogger`] prints records at.
+///
+/// Directives like `debug` set the level of all targets, `my_crate::net=trace` sets the level
+/// of a target and its submodules. The most specific directive matching the target of a
+/// record decides. All clones of a filter are changed together, e.g. by
+/// [`Builtin::LogLevel`](crate::Builtin::LogLevel).
+#[derive(Debug, Clone)]
+pub struct LogFilter {
+    state: Arc<RwLock<FilterState>>,
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        Self::new(Severity::Info)
+    }
+}
+
+impl LogFilter {
+    /// A filter printing records that are at least as severe as `level`
+    pub fn new(level: Severity) -> Self {
+        Self::from_directives(Directives {
+            default: Some(level),
+            targets: Vec::new(),
+        })
+    }
+
+    /// A filter starting at [`Severity::Info`] and changed by the comma-separated
+    /// `directives`, e.g. `warn,my_crate=debug`
+    pub fn parse(directives: &str) -> Result<Self, ParseDirectiveError> {
+        let filter = Self::default();
+        filter.set(directives)?;
+        let mut state = filter.write();
+        state.initial = state.current.clone();
+        drop(state);
+        Ok(filter)
+    }
+
+    fn from_directives(directives: Directives) -> Self {
+        Self {
+            state: Arc::new(RwLock::new(FilterState {
+                current: directives.clone(),
+                initial: directives,
+                #[cfg(feature = "log")]
+                log_installed: false,
+            })),
+        }
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, FilterState> {
+        self.state.read().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, FilterState> {
+        self.state.write().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Applies the comma-separated `directives`, replacing earlier ones for the same target.
+    /// Nothing is changed if one of them is invalid.
+    pub fn set(&self, directives: &str) -> Result<(), ParseDirectiveError> {
+        let parsed = directives
+            .split(',')
+            .filter(|directive| !directive.trim().is_empty())
+            .map(Directive::from_str)
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut state = self.write();
+        parsed
+            .into_iter()
+            .for_each(|directive| state.current.apply(directive));
+        drop(state);
+        self.update_max_level();
+        Ok(())
+    }
+
+    /// Restores the directives the filter was created with
+    pub fn reset(&self) {
+        let mut state = self.write();
+        state.current = state.initial.clone();
+        drop(state);
+        self.update_max_level();
+    }
+
+    /// The current directives, the one for all targets first
+    pub fn directives(&self) -> Vec<String> {
+        let state = self.read();
+        let default = Directive {
+            target: None,
+            level: state.current.default,
+        };
+        std::iter::once(&default)
+            .chain(&state.current.targets)
+            .map(Directive::to_string)
+            .collect()
+    }
+
+    /// Whether a record of `target` with `severity` is printed
+    pub fn enabled(&self, severity: Severity, target: &str) -> bool {
+        self.read()
+            .current
+            .level(target)
+            .is_some_and(|level| severity <= level)
+    }
+
+    #[cfg(feature = "log")]
+    fn update_max_level(&self) {
+        let state = self.read();
+        if state.log_installed {
+            let level = state.current.max_level().map(log::Level::from);
+            log::set_max_level(
+                level.map_or(log::LevelFilter::Off, |level| level.to_level_filter()),
+            );
+        }
+    }
+
+    #[cfg(not(feature = "log"))]
+    fn update_max_level(&self) {}
+}
+
+#[cfg(feature = "log")]
+impl From<log::Level> for Severity {
+    fn from(level: log::Level) -> Self {
+        match level {
+            log::Level::Error => Severity::Error,
+            log::Level::Warn => Severity::Warn,
+            log::Level::Info => Severity::Info,
+            log::Level::Debug => Severity::Debug,
+            log::Level::Trace => Severity::Trace,
+        }
+    }
+}
+
+#[cfg(feature = "log")]
+impl From<Severity> for log::Level {
+    fn from(severity: Severity) -> Self {
+        match severity {
+            Severity::Error => log::Level::Error,
+            Severity::Warn => log::Level::Warn,
+            Severity::Info => log::Level::Info,
+            Severity::Debug => log::Level::Debug,
+            Severity::Trace => log::Level::Trace,
+        }
+    }
+}
+
+#[cfg(feature = "log")]
+impl log::Log for ReplLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.filter
+            .enabled(metadata.level().into(), metadata.target())
+    }
+
+    fn log(&self, record: &log::Record) {
+        let severity = record.level().into();
+        if self.filter.enabled(severity, record.target()) {
+            self.print(severity, record.target(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(feature = "tracing-subscriber")]
+impl From<tracing::Level> for Severity {
+    fn from(level: tracing::Level) -> Self {
+        match level {
+            tracing::Level::ERROR => Severity::Error,
+            tracing::Level::WARN => Severity::Warn,
+            tracing::Level::INFO => Severity::Info,
+            tracing::Level::DEBUG => Severity::Debug,
+            tracing::Level::TRACE => Severity::Trace,
+        }
+    }
+}
+
+#[cfg(feature = "tracing-subscriber")]
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for ReplLogger {
+    // The filter is only checked here, as `enabled` would filter the events of all other layers
+    fn on_event(
+        &self,
+        event: &tracing::Event<'_>,
+        _ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        let metadata = event.metadata();
+        let severity = (*metadata.level()).into();
+        if !self.filter.enabled(severity, metadata.target()) {
+            return;
+        }
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        self.print(severity, metadata.target(), visitor.finish());
+    }
+}
+
+/// Collects the message and the other fields of a tracing event
+#[cfg(feature = "tracing-subscriber")]
+#[derive(Default)]
+struct FieldVisitor {
+    message: String,
+    fields: Vec<String>,
+}
+
+#[cfg(feature = "tracing-subscriber")]
+impl FieldVisitor {
+    fn finish(mut self) -> String {
+        self.fields.insert(0, self.message);
+        self.fields.retain(|field| !field.is_empty());
+        self.fields.join(" ")
+    }
+}
+
+#[cfg(feature = "tracing-subscriber")]
+impl tracing::field::Visit for FieldVisitor {
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        match field.name() {
+            "message" => self.message = value.to_string(),
+            name => self.fields.push(format!("{name}={value}")),
+        }
+    }
+
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        match field.name() {
+            "message" => self.message = format!("{value:?}"),
+            name => self.fields.push(format!("{name}={value:?}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_directives() {
+        let directive = |s: &str| s.parse::<Directive>().map(|d| (d.target, d.level));
+        assert_eq!(directive("debug").unwrap(), (None, Some(Severity::Debug)));
+        assert_eq!(directive(" WARN ").unwrap(), (None, Some(Severity::Warn)));
+        assert_eq!(directive("off").unwrap(), (None, None));
+        assert_eq!(
+            directive("my_crate::net = trace").unwrap(),
+            (Some("my_crate::net".into()), Some(Severity::Trace))
+        );
+        assert!(directive("verbose").is_err());
+        assert!(directive("my_crate").is_err());
+        assert!(directive("=info").is_err());
+        assert!(directive("a=b=info").is_err());
+    }
+
+    #[test]
+    fn most_specific_target_decides() {
+        let filter = LogFilter::parse("warn,app=debug,app::net=off,app::net::tcp=trace").unwrap();
+        assert!(filter.enabled(Severity::Warn, "other"));
+        assert!(!filter.enabled(Severity::Info, "other"));
+        assert!(filter.enabled(Severity::Debug, "app"));
+        assert!(filter.enabled(Severity::Debug, "app::db"));
+        assert!(!filter.enabled(Severity::Error, "app::net"));
+        assert!(!filter.enabled(Severity::Error, "app::net::udp"));
+        assert!(filter.enabled(Severity::Trace, "app::net::tcp"));
+    }
+
+    #[test]
+    fn targets_match_whole_path_segments() {
+        let filter = LogFilter::parse("error,app=trace").unwrap();
+        assert!(filter.enabled(Severity::Trace, "app::x"));
+        assert!(!filter.enabled(Severity::Trace, "apple"));
+        assert!(!filter.enabled(Severity::Trace, "app_x"));
+    }
+
+    #[test]
+    fn set_and_reset() {
+        let filter = LogFilter::parse("info,app=debug").unwrap();
+        filter.set("trace,app=warn,db=error").unwrap();
+        assert_eq!(filter.directives(), ["trace", "app=warn", "db=error"]);
+
+        assert!(filter.set("debug,bogus").is_err());
+        assert_eq!(filter.directives(), ["trace", "app=warn", "db=error"]);
+
+        filter.clone().reset();
+        assert_eq!(filter.directives(), ["info", "app=debug"]);
+    }
+
+    #[cfg(feature = "tracing-subscriber")]
+    #[test]
+    fn other_layers_see_filtered_events() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing_subscriber::util::SubscriberInitExt;
+
+        struct Counter(Arc<AtomicUsize>);
+
+        impl tracing::Subscriber for Counter {
+            fn enabled(&self, _metadata: &tracing::Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, _span: &Attributes<'_>) -> Id {
+                Id::from_u64(1)
+            }
+            fn record(&self, _span: &Id, _values: &Record<'_>) {}
+            fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+            fn event(&self, _event: &tracing::Event<'_>) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+            fn enter(&self, _span: &Id) {}
+            fn exit(&self, _span: &Id) {}
+        }
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let logger = ReplLogger::new(Printer::new(Default::default()));
+        let subscriber = tracing_subscriber::Layer::with_subscriber(logger, Counter(count.clone()));
+        let guard = subscriber.set_default();
+        tracing::debug!("below the level of the logger");
+        drop(guard);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+}
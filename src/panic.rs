@@ -0,0 +1,112 @@
+use std::any::Any;
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::cell::{Cell, RefCell};
+use std::fmt::Display;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::Once;
+
+thread_local! {
+    /// Whether a panic on this thread is caught by [`catch`]
+    static CAPTURING: Cell<bool> = const { Cell::new(false) };
+    /// The location and backtrace recorded by the hook for the panic that is unwinding
+    static CAPTURED: RefCell<Option<(Option<String>, Backtrace)>> = const { RefCell::new(None) };
+}
+
+/// A panic of a command, which was not printed to stderr by the panic hook
+#[derive(Debug)]
+pub struct PanicReport {
+    message: String,
+    location: Option<String>,
+    backtrace: Option<Backtrace>,
+    payload: Box<dyn Any + Send>,
+}
+
+impl PanicReport {
+    /// A report without location and backtrace, for panics the hook did not see
+    pub(crate) fn from_payload(payload: Box<dyn Any + Send>) -> Self {
+        Self::new(payload, None, None)
+    }
+
+    fn new(
+        payload: Box<dyn Any + Send>,
+        location: Option<String>,
+        backtrace: Option<Backtrace>,
+    ) -> Self {
+        let message = match payload.downcast_ref::<&str>() {
+            Some(message) => message.to_string(),
+            None => match payload.downcast_ref::<String>() {
+                Some(message) => message.clone(),
+                None => "Box<dyn Any>".to_string(),
+            },
+        };
+        Self {
+            message,
+            location,
+            backtrace,
+            payload,
+        }
+    }
+
+    /// The message the panic was started with
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The `file:line:column` the panic was started at
+    pub fn location(&self) -> Option<&str> {
+        self.location.as_deref()
+    }
+
+    /// The backtrace of the panic, if capturing it was enabled through `RUST_BACKTRACE` or
+    /// `RUST_LIB_BACKTRACE`
+    pub fn backtrace(&self) -> Option<&Backtrace> {
+        self.backtrace.as_ref()
+    }
+
+    /// The value the panic was started with, e.g. for [`std::panic::resume_unwind`]
+    pub fn into_payload(self) -> Box<dyn Any + Send> {
+        self.payload
+    }
+}
+
+impl Display for PanicReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.location {
+            Some(location) => write!(f, "panicked at {location}: {}", self.message),
+            None => write!(f, "panicked: {}", self.message),
+        }
+    }
+}
+
+/// Runs `f`, turning a panic into a [`PanicReport`] instead of letting the panic hook print it.
+///
+/// A hook recording panics of threads inside of this function is installed once, it passes
+/// other panics on to the hook that was installed before.
+pub(crate) fn catch<R>(f: impl FnOnce() -> R) -> Result<R, PanicReport> {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if !CAPTURING.get() {
+                return previous(info);
+            }
+            let location = info.location().map(ToString::to_string);
+            let backtrace = Backtrace::capture();
+            CAPTURED.set(Some((location, backtrace)));
+        }));
+    });
+
+    let outer = CAPTURING.replace(true);
+    let result = catch_unwind(AssertUnwindSafe(f));
+    CAPTURING.set(outer);
+    result.map_err(|payload| match CAPTURED.take() {
+        Some((location, backtrace)) => {
+            let backtrace = match backtrace.status() {
+                BacktraceStatus::Captured => Some(backtrace),
+                _ => None,
+            };
+            PanicReport::new(payload, location, backtrace)
+        }
+        None => PanicReport::from_payload(payload),
+    })
+}
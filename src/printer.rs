@@ -0,0 +1,178 @@
+use std::fmt::Display;
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use nu_ansi_term::{Color, Style};
+use reedline::ExternalPrinter;
+
+/// How important a message printed with [`Printer::log`] is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Severity {
+    pub fn label(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warn => "warn",
+            Severity::Info => "info",
+            Severity::Debug => "debug",
+            Severity::Trace => "trace",
+        }
+    }
+
+    /// The style of the label
+    pub fn style(self) -> Style {
+        match self {
+            Severity::Error => Color::Red.bold(),
+            Severity::Warn => Color::Yellow.bold(),
+            Severity::Info => Color::Green.bold(),
+            Severity::Debug => Color::Blue.bold(),
+            Severity::Trace => Color::Purple.bold(),
+        }
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// A handle for printing lines from any thread without corrupting the prompt.
+///
+/// Lines are written above the prompt through the [`ExternalPrinter`] of the
+/// [`TermReader`](crate::TermReader) while it is shown and to stdout directly otherwise, e.g.
+/// while a command executes. Styles are only applied if stdout is a terminal.
+#[derive(Debug, Clone)]
+pub struct Printer {
+    external: ExternalPrinter<String>,
+    prompt_active: Arc<AtomicBool>,
+    color: bool,
+}
+
+impl Printer {
+    pub(crate) fn new(external: ExternalPrinter<String>) -> Self {
+        Self {
+            external,
+            prompt_active: Arc::default(),
+            color: std::io::stdout().is_terminal(),
+        }
+    }
+
+    pub fn external(&self) -> &ExternalPrinter<String> {
+        &self.external
+    }
+
+    /// Whether the prompt is currently shown
+    pub fn is_prompt_active(&self) -> bool {
+        self.prompt_active.load(Ordering::SeqCst)
+    }
+
+    /// Marks the prompt as shown until the returned guard is dropped
+    pub(crate) fn activate_prompt(&self) -> PromptGuard {
+        self.prompt_active.store(true, Ordering::SeqCst);
+        PromptGuard(self.prompt_active.clone())
+    }
+
+    /// Prints a line, waiting for the editor to drain the external printer if it is full
+    pub fn print(&self, display: impl Display) {
+        let mut line = display.to_string();
+        let sender = self.external.sender();
+        while self.is_prompt_active() {
+            match sender.send_timeout(line, Duration::from_millis(50)) {
+                Ok(()) => return,
+                // The prompt may have been closed in the meantime
+                Err(err) if err.is_timeout() => line = err.into_inner(),
+                Err(err) => {
+                    line = err.into_inner();
+                    break;
+                }
+            }
+        }
+        write_stdout(&line);
+    }
+
+    /// Prints a line without blocking, returning it if the external printer is full
+    pub fn try_print(&self, display: impl Display) -> Result<(), String> {
+        let mut line = display.to_string();
+        if self.is_prompt_active() {
+            match self.external.sender().try_send(line) {
+                Ok(()) => return Ok(()),
+                Err(err) if err.is_full() => return Err(err.into_inner()),
+                Err(err) => line = err.into_inner(),
+            }
+        }
+        write_stdout(&line);
+        Ok(())
+    }
+
+    /// Prints a line in `style`
+    pub fn print_styled(&self, style: Style, display: impl Display) {
+        self.print(self.paint(style, display));
+    }
+
+    /// Prints a line prefixed with the colored label of `severity`
+    pub fn log(&self, severity: Severity, display: impl Display) {
+        self.print(self.format_log(severity, display));
+    }
+
+    /// Like [`Printer::log`], but without blocking like [`Printer::try_print`]
+    pub fn try_log(&self, severity: Severity, display: impl Display) -> Result<(), String> {
+        self.try_print(self.format_log(severity, display))
+    }
+
+    pub fn error(&self, display: impl Display) {
+        self.log(Severity::Error, display);
+    }
+
+    pub fn warn(&self, display: impl Display) {
+        self.log(Severity::Warn, display);
+    }
+
+    pub fn info(&self, display: impl Display) {
+        self.log(Severity::Info, display);
+    }
+
+    pub fn debug(&self, display: impl Display) {
+        self.log(Severity::Debug, display);
+    }
+
+    /// Whether styles are applied to printed lines
+    pub fn is_colored(&self) -> bool {
+        self.color
+    }
+
+    pub(crate) fn paint(&self, style: Style, display: impl Display) -> String {
+        if self.color {
+            style.paint(display.to_string()).to_string()
+        } else {
+            display.to_string()
+        }
+    }
+
+    fn format_log(&self, severity: Severity, display: impl Display) -> String {
+        format!("{}: {display}", self.paint(severity.style(), severity))
+    }
+}
+
+fn write_stdout(line: &str) {
+    let mut stdout = std::io::stdout().lock();
+    let _ = writeln!(stdout, "{line}");
+    let _ = stdout.flush();
+}
+
+pub(crate) struct PromptGuard(Arc<AtomicBool>);
+
+impl Drop for PromptGuard {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,226 @@
+use std::fmt::{Debug, Display};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Lines};
+use std::path::{Path, PathBuf};
+
+use clap::Command;
+
+use crate::cancel;
+use crate::{ControlFlow, ErrorContext, ErrorHandler, ExitStatus, ReplContext, ReplError};
+
+/// How deeply `source` calls can be nested by default
+pub const DEFAULT_MAX_SOURCE_DEPTH: usize = 16;
+
+/// What happens when a line of a script fails
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScriptErrorPolicy {
+    /// Stop executing the script and return the error
+    #[default]
+    Stop,
+    /// Pass the error to the error handler and continue with the next line
+    Continue,
+}
+
+/// The line of a script an error occurred in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptLocation {
+    pub file: Option<PathBuf>,
+    /// The 1-based line number
+    pub line: usize,
+}
+
+impl Display for ScriptLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}", file.display(), self.line),
+            None => write!(f, "line {}", self.line),
+        }
+    }
+}
+
+impl<C: clap::Parser + Debug, Err: Debug + Display> ReplContext<C, Err> {
+    pub fn set_script_error_policy(&mut self, policy: ScriptErrorPolicy) {
+        self.script_error_policy = policy;
+    }
+
+    /// Sets how deeply files can be nested through the `source` builtin and
+    /// [`ReplContext::run_script`]
+    pub fn set_max_source_depth(&mut self, depth: usize) {
+        self.max_source_depth = depth;
+    }
+
+    /// Executes the commands in the file at `path`, see [`ReplContext::run_lines`]
+    pub fn run_script<F: ErrorHandler<Err>>(
+        &mut self,
+        path: impl AsRef<Path>,
+        handle_error: F,
+    ) -> Result<ExitStatus, ReplError<Err>> {
+        let mut command = self.command.clone();
+        let policy = self.script_error_policy;
+        let mut failed = false;
+        let flow = self.run_file(&mut command, path.as_ref(), |context, error| {
+            failed = true;
+            match policy {
+                ScriptErrorPolicy::Stop => Err(error),
+                ScriptErrorPolicy::Continue => handle_error.handle(context, error),
+            }
+        })?;
+        Ok(exit_status(flow, failed))
+    }
+
+    /// Executes every line like a command typed into the REPL, skipping blank lines and
+    /// lines starting with `#`.
+    ///
+    /// Errors are wrapped in [`ReplError::Script`] with the number of the failing line and
+    /// either returned or passed to `handle_error` depending on the [`ScriptErrorPolicy`].
+    /// Returns the status requested by an `exit` or, once all lines ran, a failure status if
+    /// any line failed.
+    pub fn run_lines<F: ErrorHandler<Err>>(
+        &mut self,
+        lines: impl IntoIterator<Item = impl AsRef<str>>,
+        handle_error: F,
+    ) -> Result<ExitStatus, ReplError<Err>> {
+        let mut command = self.command.clone();
+        let policy = self.script_error_policy;
+        let mut failed = false;
+        let lines = lines.into_iter().map(Ok);
+        let flow = self.run_script_lines(&mut command, None, lines, |context, error| {
+            failed = true;
+            match policy {
+                ScriptErrorPolicy::Stop => Err(error),
+                ScriptErrorPolicy::Continue => handle_error.handle(context, error),
+            }
+        })?;
+        Ok(exit_status(flow, failed))
+    }
+
+    /// Executes the lines of stdin if it is not a terminal, see [`ReplContext::read_loop`]
+    pub(crate) fn read_plain_loop<F: ErrorHandler<Err>>(
+        &mut self,
+        handle_error: F,
+    ) -> Result<ExitStatus, ReplError<Err>> {
+        let _loop = cancel::enter_loop();
+        self.interactive = false;
+        let mut command = self.command.clone();
+        let mut failed = false;
+        let lines = std::io::stdin().lines();
+        let flow = self.run_script_lines(&mut command, None, lines, |context, error| {
+            failed = true;
+            handle_error.handle(context, error)
+        });
+        self.interactive = true;
+        match flow {
+            Ok(flow) => Ok(exit_status(flow, failed)),
+            Err(ReplError::EOF) => Ok(exit_status(ControlFlow::Continue, failed)),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Executes the file at `path` for the `source` builtin, stopping at the first error
+    pub(crate) fn source(
+        &mut self,
+        command: &mut Command,
+        path: &Path,
+    ) -> Result<ControlFlow, ReplError<Err>> {
+        self.run_file(command, path, |_, error| Err(error))
+    }
+
+    fn run_file(
+        &mut self,
+        command: &mut Command,
+        path: &Path,
+        on_error: impl FnMut(&mut ErrorContext<'_>, ReplError<Err>) -> Result<(), ReplError<Err>>,
+    ) -> Result<ControlFlow, ReplError<Err>> {
+        let lines = self.open_script(path)?;
+        let result = self.run_script_lines(command, Some(path), lines, on_error);
+        self.source_stack.pop();
+        result
+    }
+
+    /// Opens the file at `path` and pushes it onto the stack of running scripts, which the
+    /// caller pops once it is done
+    pub(crate) fn open_script(
+        &mut self,
+        path: &Path,
+    ) -> Result<Lines<BufReader<File>>, ReplError<Err>> {
+        let script_file = |error| ReplError::ScriptFile {
+            path: path.to_path_buf(),
+            error,
+        };
+        let canonical = path.canonicalize().map_err(script_file)?;
+        if self.source_stack.contains(&canonical) {
+            return Err(ReplError::SourceCycle(path.to_path_buf()));
+        }
+        if self.source_stack.len() >= self.max_source_depth {
+            return Err(ReplError::SourceDepth(self.max_source_depth));
+        }
+        let reader = BufReader::new(File::open(path).map_err(script_file)?);
+        self.source_stack.push(canonical);
+        Ok(reader.lines())
+    }
+
+    /// Executes `lines` until one of them requests to exit or an error is not handled by
+    /// `on_error`
+    fn run_script_lines(
+        &mut self,
+        command: &mut Command,
+        file: Option<&Path>,
+        lines: impl Iterator<Item = std::io::Result<impl AsRef<str>>>,
+        mut on_error: impl FnMut(&mut ErrorContext<'_>, ReplError<Err>) -> Result<(), ReplError<Err>>,
+    ) -> Result<ControlFlow, ReplError<Err>> {
+        for (idx, line) in lines.enumerate() {
+            let (line, result) = match line {
+                Ok(line) => {
+                    let line = line.as_ref().trim().to_string();
+                    let result = if is_comment(&line) {
+                        Ok(ControlFlow::Continue)
+                    } else {
+                        self.execute_line(command, &line)
+                    };
+                    (Some(line), result)
+                }
+                Err(err) => (None, Err(ReplError::from(err))),
+            };
+
+            match result {
+                Ok(ControlFlow::Continue) => {}
+                Ok(ControlFlow::Exit(status)) => return Ok(ControlFlow::Exit(status)),
+                Ok(ControlFlow::Restart) => self.restart(command),
+                Err(error) => {
+                    let error = script_error(file, idx, error);
+                    let mut context = self.error_context(command, line.as_deref());
+                    on_error(&mut context, error)?
+                }
+            }
+        }
+        Ok(ControlFlow::Continue)
+    }
+}
+
+/// Whether `line` is skipped because it is blank or a comment
+pub(crate) fn is_comment(line: &str) -> bool {
+    line.is_empty() || line.starts_with('#')
+}
+
+/// Wraps the `error` of the line at the 0-based `idx` in [`ReplError::Script`]
+pub(crate) fn script_error<Err: Debug + Display>(
+    file: Option<&Path>,
+    idx: usize,
+    error: ReplError<Err>,
+) -> ReplError<Err> {
+    ReplError::Script {
+        location: ScriptLocation {
+            file: file.map(Path::to_path_buf),
+            line: idx + 1,
+        },
+        error: Box::new(error),
+    }
+}
+
+pub(crate) fn exit_status(flow: ControlFlow, failed: bool) -> ExitStatus {
+    match flow {
+        ControlFlow::Exit(status) => status,
+        _ if failed => ExitStatus(1),
+        _ => ExitStatus::SUCCESS,
+    }
+}
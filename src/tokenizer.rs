@@ -0,0 +1,296 @@
+use std::ops::Range;
+
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum TokenizeError {
+    #[error("Unterminated {quote} quote starting at column {column}")]
+    UnterminatedQuote { quote: char, column: usize },
+    #[error("Dangling escape character at column {column}")]
+    DanglingEscape { column: usize },
+    #[error("{message} at column {column}")]
+    Custom { message: String, column: usize },
+}
+
+impl TokenizeError {
+    /// The 1-based column of the character that caused the error
+    pub fn column(&self) -> usize {
+        match self {
+            TokenizeError::UnterminatedQuote { column, .. } => *column,
+            TokenizeError::DanglingEscape { column } => *column,
+            TokenizeError::Custom { column, .. } => *column,
+        }
+    }
+}
+
+/// A single argument produced by a [`Tokenizer`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    /// The argument with quotes and escapes resolved
+    pub value: String,
+    /// Byte range of the raw token in the input line, including any quotes
+    pub span: Range<usize>,
+}
+
+impl Token {
+    pub fn new(value: impl Into<String>, span: Range<usize>) -> Self {
+        Self {
+            value: value.into(),
+            span,
+        }
+    }
+
+    /// The 1-based column at which the token starts in `line`
+    pub fn column(&self, line: &str) -> usize {
+        column_at(line, self.span.start)
+    }
+}
+
+/// Converts a byte offset into `line` to a 1-based character column
+pub fn column_at(line: &str, offset: usize) -> usize {
+    line[..offset].chars().count() + 1
+}
+
+/// Splits a command line into the arguments that get passed to clap
+pub trait Tokenizer {
+    fn tokenize(&self, line: &str) -> Result<Vec<Token>, TokenizeError>;
+
+    /// Tokenizes a line that is still being edited, e.g. for completions.
+    ///
+    /// Unlike [`Tokenizer::tokenize`] this should not fail on input that is merely
+    /// unfinished, like a quote that has not been closed yet.
+    fn tokenize_incomplete(&self, line: &str) -> Vec<Token> {
+        self.tokenize(line).unwrap_or_default()
+    }
+
+    /// Quotes `value` so that it is tokenized back into a single argument
+    fn quote(&self, value: &str) -> String {
+        value.to_string()
+    }
+}
+
+impl<F: Fn(&str) -> Result<Vec<Token>, TokenizeError>> Tokenizer for F {
+    fn tokenize(&self, line: &str) -> Result<Vec<Token>, TokenizeError> {
+        self(line)
+    }
+}
+
+/// Splits a command line into arguments the way a POSIX shell would.
+///
+/// Arguments are separated by unquoted whitespace. Text inside single quotes is taken
+/// literally, inside double quotes a backslash only escapes `"` and `\`, and outside of
+/// quotes a backslash escapes any following character. Quoted segments directly adjacent
+/// to other text are joined into one argument and `""` or `''` produce an empty argument.
+///
+/// Values starting with `~` are quoted by [`Tokenizer::quote`], as the
+/// [`ReplContext`](crate::ReplContext) replaces an unquoted `~` at the start of a path with the
+/// home directory.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ShellTokenizer;
+
+impl Tokenizer for ShellTokenizer {
+    fn tokenize(&self, line: &str) -> Result<Vec<Token>, TokenizeError> {
+        shell_tokenize(line, false)
+    }
+
+    fn tokenize_incomplete(&self, line: &str) -> Vec<Token> {
+        shell_tokenize(line, true).unwrap_or_default()
+    }
+
+    fn quote(&self, value: &str) -> String {
+        if value.is_empty() {
+            "''".to_string()
+        } else if !value.starts_with('~')
+            && !value
+                .chars()
+                .any(|ch| ch.is_whitespace() || matches!(ch, '\'' | '"' | '\\'))
+        {
+            value.to_string()
+        } else if !value.contains('\'') {
+            format!("'{value}'")
+        } else {
+            format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+        }
+    }
+}
+
+/// Tokenizes `line`, ending unterminated quotes at the end of the line if `incomplete` is set
+fn shell_tokenize(line: &str, incomplete: bool) -> Result<Vec<Token>, TokenizeError> {
+    let mut tokens = Vec::new();
+    let mut current: Option<(String, usize)> = None;
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((idx, ch)) = chars.next() {
+        if ch.is_whitespace() {
+            if let Some((value, start)) = current.take() {
+                tokens.push(Token::new(value, start..idx));
+            }
+            continue;
+        }
+
+        let (arg, _) = current.get_or_insert_with(|| (String::new(), idx));
+        match ch {
+            '\\' => match chars.next() {
+                Some((_, escaped)) => arg.push(escaped),
+                None if incomplete => {}
+                None => {
+                    return Err(TokenizeError::DanglingEscape {
+                        column: column_at(line, idx),
+                    })
+                }
+            },
+            '\'' => loop {
+                match chars.next() {
+                    Some((_, '\'')) => break,
+                    Some((_, ch)) => arg.push(ch),
+                    None if incomplete => break,
+                    None => {
+                        return Err(TokenizeError::UnterminatedQuote {
+                            quote: '\'',
+                            column: column_at(line, idx),
+                        })
+                    }
+                }
+            },
+            '"' => loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    Some((_, '\\')) => match chars.peek() {
+                        Some((_, escaped @ ('"' | '\\'))) => {
+                            arg.push(*escaped);
+                            chars.next();
+                        }
+                        _ => arg.push('\\'),
+                    },
+                    Some((_, ch)) => arg.push(ch),
+                    None if incomplete => break,
+                    None => {
+                        return Err(TokenizeError::UnterminatedQuote {
+                            quote: '"',
+                            column: column_at(line, idx),
+                        })
+                    }
+                }
+            },
+            ch => arg.push(ch),
+        }
+    }
+
+    if let Some((value, start)) = current {
+        tokens.push(Token::new(value, start..line.len()));
+    }
+    Ok(tokens)
+}
+
+/// Splits `line` using the [`ShellTokenizer`]
+pub fn split(line: &str) -> Result<Vec<String>, TokenizeError> {
+    ShellTokenizer
+        .tokenize(line)
+        .map(|tokens| tokens.into_iter().map(|token| token.value).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_on_whitespace() {
+        assert_eq!(split("  foo bar\tbaz  ").unwrap(), ["foo", "bar", "baz"]);
+        assert!(split("").unwrap().is_empty());
+        assert!(split("   ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn quotes() {
+        assert_eq!(split("say 'a b' \"c d\"").unwrap(), ["say", "a b", "c d"]);
+        assert_eq!(split("'a \\ \"b\"'").unwrap(), ["a \\ \"b\""]);
+        assert_eq!(split("\"a \\\" \\\\ \\n\"").unwrap(), ["a \" \\ \\n"]);
+        assert_eq!(
+            split("pre'quoted'\"joined\"post").unwrap(),
+            ["prequotedjoinedpost"]
+        );
+    }
+
+    #[test]
+    fn empty_arguments() {
+        assert_eq!(split("a '' \"\" b").unwrap(), ["a", "", "", "b"]);
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(split("a\\ b c\\'d").unwrap(), ["a b", "c'd"]);
+        assert_eq!(split("\\\\").unwrap(), ["\\"]);
+    }
+
+    #[test]
+    fn spans_include_quotes() {
+        let tokens = ShellTokenizer.tokenize("ab 'c d'  e").unwrap();
+        assert_eq!(
+            tokens,
+            [
+                Token::new("ab", 0..2),
+                Token::new("c d", 3..8),
+                Token::new("e", 10..11),
+            ]
+        );
+    }
+
+    #[test]
+    fn columns_count_characters() {
+        let line = "äö 'x";
+        let tokens = ShellTokenizer.tokenize_incomplete(line);
+        assert_eq!(tokens[1].column(line), 4);
+        assert_eq!(
+            ShellTokenizer.tokenize(line),
+            Err(TokenizeError::UnterminatedQuote {
+                quote: '\'',
+                column: 4
+            })
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            ShellTokenizer.tokenize("a \"b"),
+            Err(TokenizeError::UnterminatedQuote {
+                quote: '"',
+                column: 3
+            })
+        );
+        assert_eq!(
+            ShellTokenizer.tokenize("a b\\"),
+            Err(TokenizeError::DanglingEscape { column: 4 })
+        );
+    }
+
+    #[test]
+    fn incomplete_lines() {
+        let values = |line| -> Vec<_> {
+            let tokens = ShellTokenizer.tokenize_incomplete(line);
+            tokens.into_iter().map(|token| token.value).collect()
+        };
+        assert_eq!(values("say \"a b"), ["say", "a b"]);
+        assert_eq!(values("say 'a"), ["say", "a"]);
+        assert_eq!(values("say a\\"), ["say", "a"]);
+    }
+
+    #[test]
+    fn quoted_values_are_tokenized_back() {
+        for value in [
+            "",
+            "plain",
+            "a b",
+            "it's",
+            "\"q\"",
+            "a\\b",
+            "it's \"both\"",
+            "~",
+            "~/a",
+        ] {
+            let quoted = ShellTokenizer.quote(value);
+            assert_eq!(split(&quoted).unwrap(), [value], "{quoted}");
+        }
+        assert_eq!(ShellTokenizer.quote("~"), "'~'");
+    }
+}